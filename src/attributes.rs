@@ -0,0 +1,311 @@
+use crate::config::MAX_ATTRIBUTE_WEIGHT;
+use crate::helpers::{command_span, handle_error, respond};
+use crate::matching::PairScore;
+use crate::permissions::{require_configure, require_view};
+use crate::program::{autocomplete_program, DEFAULT_PROGRAM};
+use crate::storage::{Attribute, AttributeMode};
+use crate::types::Context;
+use anyhow::{bail, ensure, Context as _, Result};
+use itertools::Itertools;
+use poise::ChoiceParameter as _;
+use serenity::all::{Role, RoleId, UserId};
+use std::collections::{HashMap, HashSet};
+use tracing::Instrument;
+
+/// Creates a score function that rewards matches according to the guild's attributes.
+/// `members` contains the roles of each participant.
+pub fn attribute_score(
+    attributes: Vec<Attribute>,
+    members: &[(UserId, Vec<RoleId>)],
+) -> PairScore<UserId> {
+    // the values of each attribute for each member, in the same order as `attributes`
+    let values: HashMap<UserId, Vec<HashSet<RoleId>>> = members
+        .iter()
+        .map(|(id, roles)| {
+            (
+                *id,
+                attributes
+                    .iter()
+                    .map(|a| {
+                        roles
+                            .iter()
+                            .filter(|r| a.roles.contains(r))
+                            .cloned()
+                            .collect()
+                    })
+                    .collect(),
+            )
+        })
+        .collect();
+
+    Box::new(move |a, b| {
+        let (Some(a_values), Some(b_values)) = (values.get(a), values.get(b)) else {
+            return 0;
+        };
+        attributes
+            .iter()
+            .zip(a_values.iter().zip(b_values))
+            .filter(|(_, (a, b))| !a.is_empty() && !b.is_empty())
+            .map(|(attribute, (a, b))| {
+                let shares_value = !a.is_disjoint(b);
+                match (attribute.mode, shares_value) {
+                    // weights saved before they were capped are capped here too
+                    (AttributeMode::Mix, false) | (AttributeMode::Same, true) => {
+                        attribute.weight.min(MAX_ATTRIBUTE_WEIGHT)
+                    }
+                    _ => 0,
+                }
+            })
+            .sum()
+    })
+}
+
+async fn handle_set_attribute(
+    ctx: Context<'_>,
+    program: &str,
+    name: String,
+    mode: AttributeMode,
+    weight: i64,
+) -> Result<String> {
+    let guild_id = ctx
+        .guild_id()
+        .context("This command must be called from a guild (server).")?;
+    ensure!(
+        (1..=MAX_ATTRIBUTE_WEIGHT).contains(&weight),
+        "The weight must be between 1 and {MAX_ATTRIBUTE_WEIGHT}."
+    );
+    ctx.data().store.update_program(guild_id, program, |data| {
+        match data.attributes.iter_mut().find(|a| a.name == name) {
+            Some(attribute) => {
+                attribute.mode = mode;
+                attribute.weight = weight;
+            }
+            None => data.attributes.push(Attribute {
+                name: name.clone(),
+                mode,
+                weight,
+                roles: Vec::new(),
+            }),
+        }
+    })?;
+    Ok(format!(
+        "Attribute `{name}` is set to `{}` with weight {weight}.",
+        mode.name()
+    ))
+}
+
+async fn handle_set_role(
+    ctx: Context<'_>,
+    program: &str,
+    name: String,
+    role: Role,
+    add: bool,
+) -> Result<String> {
+    let guild_id = ctx
+        .guild_id()
+        .context("This command must be called from a guild (server).")?;
+    let found = ctx.data().store.update_program(guild_id, program, |data| {
+        let Some(attribute) = data.attributes.iter_mut().find(|a| a.name == name) else {
+            return false;
+        };
+        attribute.roles.retain(|r| *r != role.id);
+        if add {
+            attribute.roles.push(role.id);
+        }
+        true
+    })?;
+    if !found {
+        bail!("There is no attribute named `{name}`.");
+    }
+    Ok(if add {
+        format!("Added <@&{}> to `{name}`.", role.id)
+    } else {
+        format!("Removed <@&{}> from `{name}`.", role.id)
+    })
+}
+
+async fn handle_remove_attribute(ctx: Context<'_>, program: &str, name: String) -> Result<String> {
+    let guild_id = ctx
+        .guild_id()
+        .context("This command must be called from a guild (server).")?;
+    let removed = ctx.data().store.update_program(guild_id, program, |data| {
+        let before = data.attributes.len();
+        data.attributes.retain(|a| a.name != name);
+        before != data.attributes.len()
+    })?;
+    if !removed {
+        bail!("There is no attribute named `{name}`.");
+    }
+    Ok(format!("Removed attribute `{name}`."))
+}
+
+async fn handle_list_attributes(ctx: Context<'_>, program: &str) -> Result<String> {
+    let guild_id = ctx
+        .guild_id()
+        .context("This command must be called from a guild (server).")?;
+    ctx.data().store.read_program(guild_id, program, |data| {
+        if data.attributes.is_empty() {
+            return "No attributes are configured.".to_owned();
+        }
+        data.attributes
+            .iter()
+            .map(|a| {
+                format!(
+                    "`{}` ({}, weight {}): {}",
+                    a.name,
+                    a.mode.name(),
+                    a.weight,
+                    a.roles.iter().map(|r| format!("<@&{r}>")).join(", ")
+                )
+            })
+            .join("\n")
+    })
+}
+
+/// Manage member attributes (based on roles) which are used to mix or group members
+#[poise::command(
+    slash_command,
+    hide_in_help,
+    ephemeral,
+    check = "require_view",
+    subcommands("set", "add_role", "remove_role", "remove", "list"),
+    on_error = "handle_error"
+)]
+pub async fn matching_attributes(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Create or update an attribute
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_configure",
+    on_error = "handle_error"
+)]
+async fn set(
+    ctx: Context<'_>,
+    #[description = "The name of the attribute (for example, \"team\")."] name: String,
+    #[description = "Whether to favor matching members with different or the same values."]
+    mode: AttributeMode,
+    #[description = "How strongly this attribute affects matching, from 1 to 10 (default 1)."]
+    weight: Option<i64>,
+    #[description = "The program (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
+) -> Result<()> {
+    let resp = handle_set_attribute(
+        ctx,
+        program.as_deref().unwrap_or(DEFAULT_PROGRAM),
+        name,
+        mode,
+        weight.unwrap_or(1),
+    )
+    .instrument(command_span(ctx))
+    .await;
+    respond(ctx, resp).await
+}
+
+/// Add a role as a possible value of an attribute
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_configure",
+    on_error = "handle_error"
+)]
+async fn add_role(
+    ctx: Context<'_>,
+    #[description = "The name of the attribute."] name: String,
+    #[description = "The role to add."] role: Role,
+    #[description = "The program (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
+) -> Result<()> {
+    respond(
+        ctx,
+        handle_set_role(
+            ctx,
+            program.as_deref().unwrap_or(DEFAULT_PROGRAM),
+            name,
+            role,
+            true,
+        )
+        .instrument(command_span(ctx))
+        .await,
+    )
+    .await
+}
+
+/// Remove a role from an attribute
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_configure",
+    on_error = "handle_error"
+)]
+async fn remove_role(
+    ctx: Context<'_>,
+    #[description = "The name of the attribute."] name: String,
+    #[description = "The role to remove."] role: Role,
+    #[description = "The program (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
+) -> Result<()> {
+    respond(
+        ctx,
+        handle_set_role(
+            ctx,
+            program.as_deref().unwrap_or(DEFAULT_PROGRAM),
+            name,
+            role,
+            false,
+        )
+        .instrument(command_span(ctx))
+        .await,
+    )
+    .await
+}
+
+/// Remove an attribute
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_configure",
+    on_error = "handle_error"
+)]
+async fn remove(
+    ctx: Context<'_>,
+    #[description = "The name of the attribute."] name: String,
+    #[description = "The program (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
+) -> Result<()> {
+    respond(
+        ctx,
+        handle_remove_attribute(ctx, program.as_deref().unwrap_or(DEFAULT_PROGRAM), name)
+            .instrument(command_span(ctx))
+            .await,
+    )
+    .await
+}
+
+/// List the attributes configured for this server
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_view",
+    on_error = "handle_error"
+)]
+async fn list(
+    ctx: Context<'_>,
+    #[description = "The program (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
+) -> Result<()> {
+    respond(
+        ctx,
+        handle_list_attributes(ctx, program.as_deref().unwrap_or(DEFAULT_PROGRAM))
+            .instrument(command_span(ctx))
+            .await,
+    )
+    .await
+}
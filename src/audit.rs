@@ -0,0 +1,199 @@
+use crate::guild_api::{GuildApi, SerenityGuild};
+use crate::types::{Context, Data};
+use anyhow::{Error, Result};
+use itertools::Itertools;
+use serenity::all::{ChannelId, ResolvedValue, Timestamp, UserId};
+use tracing::{info, warn};
+
+/// An admin action, recorded in the audit channel if one is configured.
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    /// The member who took the action
+    pub actor: UserId,
+    /// The command that was used, such as `/send_pairing`
+    pub action: String,
+    pub parameters: Vec<(String, String)>,
+    /// What happened, such as the key that was created or the error
+    pub outcome: String,
+    pub time: Timestamp,
+}
+
+impl AuditEntry {
+    /// Creates an entry for the command being run in the context, with the arguments it was given.
+    pub fn for_command(ctx: Context<'_>, outcome: impl Into<String>) -> Self {
+        let parameters = match ctx {
+            poise::Context::Application(app) => app
+                .args
+                .iter()
+                .map(|arg| (arg.name.to_owned(), format_value(&arg.value)))
+                .collect(),
+            poise::Context::Prefix(_) => vec![("invocation".to_owned(), ctx.invocation_string())],
+        };
+        AuditEntry {
+            actor: ctx.author().id,
+            action: format!("/{}", ctx.command().qualified_name),
+            parameters,
+            outcome: outcome.into(),
+            time: Timestamp::now(),
+        }
+    }
+
+    /// Formats the entry as a message for the audit channel.
+    pub fn format(&self) -> String {
+        let parameters = if self.parameters.is_empty() {
+            "none".to_owned()
+        } else {
+            self.parameters
+                .iter()
+                .map(|(name, value)| format!("{name}: `{value}`"))
+                .join(", ")
+        };
+        format!(
+            "**{}** by <@{}> at <t:{}:F>\n**Parameters:** {parameters}\n**Outcome:** {}",
+            self.action,
+            self.actor,
+            self.time.unix_timestamp(),
+            self.outcome
+        )
+    }
+}
+
+/// Formats an argument of a slash command for an audit entry.
+fn format_value(value: &ResolvedValue) -> String {
+    match value {
+        ResolvedValue::Boolean(b) => b.to_string(),
+        ResolvedValue::Integer(i) => i.to_string(),
+        ResolvedValue::Number(n) => n.to_string(),
+        ResolvedValue::String(s) => s.to_string(),
+        ResolvedValue::Attachment(attachment) => attachment.filename.clone(),
+        ResolvedValue::Channel(channel) => channel.id.to_string(),
+        ResolvedValue::Role(role) => role.name.clone(),
+        ResolvedValue::User(user, _) => user.id.to_string(),
+        other => format!("{other:?}"),
+    }
+}
+
+/// Posts the entry to the channel, if there is one. Failing to record an entry is logged rather
+/// than returned, so it doesn't change the outcome of the action.
+async fn post_entry(api: &impl GuildApi, channel: Option<ChannelId>, entry: &AuditEntry) {
+    info!(
+        actor = %entry.actor,
+        action = entry.action,
+        outcome = entry.outcome,
+        "Admin action"
+    );
+    let Some(channel) = channel else { return };
+    if let Err(e) = api.post(channel, entry.format()).await {
+        warn!("Could not record an admin action in the audit channel: {e:#}");
+    }
+}
+
+/// Posts the entry to the guild's audit channel, if one is configured.
+pub async fn record_audit(api: &impl GuildApi, data: &Data, entry: &AuditEntry) {
+    let channel = data
+        .store
+        .read(api.guild_id(), |data| data.settings.audit_channel);
+    post_entry(api, channel, entry).await;
+}
+
+/// An admin action that is in progress. It is recorded in the audit channel that was configured
+/// when it started, so that changing the audit channel is recorded in the old one.
+#[derive(Debug, Clone)]
+pub struct PendingAudit {
+    pub channel: Option<ChannelId>,
+    /// The entry to record, whose outcome is filled in once the action has finished
+    pub entry: AuditEntry,
+    /// Whether the action has set its outcome
+    responded: bool,
+}
+
+impl PendingAudit {
+    pub fn start(api: &impl GuildApi, data: &Data, entry: AuditEntry) -> Self {
+        let channel = data
+            .store
+            .read(api.guild_id(), |data| data.settings.audit_channel);
+        PendingAudit {
+            channel,
+            entry,
+            responded: false,
+        }
+    }
+
+    pub fn set_outcome(&mut self, outcome: impl Into<String>) {
+        self.entry.outcome = outcome.into();
+        self.responded = true;
+    }
+
+    /// Records that the action failed, keeping the outcome it set before failing (such as a round
+    /// that was sent before the reply to the organizer failed).
+    pub fn set_error(&mut self, error: &Error) {
+        self.entry.outcome = if self.responded {
+            format!("{}\nThen failed: {error}", self.entry.outcome)
+        } else {
+            format!("Error: {error}")
+        };
+    }
+
+    pub async fn record(&self, api: &impl GuildApi) {
+        post_entry(api, self.channel, &self.entry).await;
+    }
+}
+
+/// Marks the command as an admin action, to be recorded once it has finished. This is done by the
+/// permission checks above `view`, and can be used as a check by other commands.
+pub async fn audited(ctx: Context<'_>) -> Result<bool> {
+    if ctx.invocation_data::<PendingAudit>().await.is_none() {
+        if let Ok(api) = SerenityGuild::new(ctx) {
+            let entry = AuditEntry::for_command(ctx, "No response");
+            let pending = PendingAudit::start(&api, ctx.data(), entry);
+            ctx.set_invocation_data(pending).await;
+        }
+    }
+    Ok(true)
+}
+
+/// Sets the outcome of the command, if it is an admin action.
+pub async fn set_audit_outcome(ctx: Context<'_>, outcome: impl Into<String>) {
+    if let Some(mut pending) = ctx.invocation_data::<PendingAudit>().await {
+        pending.set_outcome(outcome);
+    }
+}
+
+/// Records the command if it is an admin action. This runs after every command that finishes.
+pub async fn record_command(ctx: Context<'_>) {
+    let Some(pending) = ctx
+        .invocation_data::<PendingAudit>()
+        .await
+        .map(|p| p.clone())
+    else {
+        return;
+    };
+    if let Ok(api) = SerenityGuild::new(ctx) {
+        pending.record(&api).await;
+    }
+}
+
+/// Records the command if it is an admin action that returned an error. poise doesn't run the
+/// post_command hook for these, so this is called by the error handler.
+pub async fn record_failed_command(ctx: Context<'_>, error: &Error) {
+    let Some(mut pending) = ctx
+        .invocation_data::<PendingAudit>()
+        .await
+        .map(|p| p.clone())
+    else {
+        return;
+    };
+    pending.set_error(error);
+    if let Ok(api) = SerenityGuild::new(ctx) {
+        pending.record(&api).await;
+    }
+}
+
+/// Records a command that was not run because the member failed a permission check.
+pub async fn record_denied(ctx: Context<'_>, reason: String) {
+    let Ok(api) = SerenityGuild::new(ctx) else {
+        return;
+    };
+    let entry = AuditEntry::for_command(ctx, format!("Denied: {reason}"));
+    record_audit(&api, ctx.data(), &entry).await;
+}
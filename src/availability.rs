@@ -0,0 +1,268 @@
+use crate::helpers::{command_span, handle_error, respond};
+use crate::matching::PairScore;
+use crate::storage::{Profile, Schedule, WeeklyWindow};
+use crate::types::Context;
+use anyhow::{bail, Context as _, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use itertools::Itertools;
+use serenity::all::{Timestamp, UserId};
+use std::collections::HashMap;
+use tracing::Instrument;
+
+/// The penalty for matching two members whose weekly availability never overlaps
+const NO_OVERLAP_PENALTY: i64 = 3;
+
+/// The maximum number of overlapping windows to suggest in a DM
+const MAX_SUGGESTED_WINDOWS: usize = 5;
+
+const MINUTES_PER_DAY: i64 = 24 * 60;
+const MINUTES_PER_WEEK: i64 = 7 * MINUTES_PER_DAY;
+
+const DAY_NAMES: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+/// A half-open interval of minutes since the start of the reference week, in UTC.
+pub type Interval = (i64, i64);
+
+/// The start (Monday 00:00 UTC) of the week schedules are compared in. Windows are converted to
+/// UTC on this week's dates, so that each time zone's daylight saving rules are applied for about
+/// the right time of year. The week comes from the seed if it starts with a date (such as
+/// `2024-03-11`), and otherwise from the program's last round, rather than from the current time,
+/// so that /create_pairing and /send_pairing agree even if they are used in different weeks.
+/// Without either, the first week of 2024 is used.
+pub fn reference_week(seed_str: &str, last_round: Option<Timestamp>) -> DateTime<Utc> {
+    let seed_date = seed_str
+        .get(..10)
+        .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok());
+    let day = seed_date
+        .or(last_round.map(|t| t.date_naive()))
+        .unwrap_or(NaiveDate::from_ymd_opt(2024, 1, 1).expect("the date should be valid"));
+    let monday = day.week(Weekday::Mon).first_day();
+    monday.and_time(NaiveTime::MIN).and_utc()
+}
+
+/// Parses a day specifier into the days (since Monday) it refers to.
+fn parse_days(days: &str) -> Option<Vec<u8>> {
+    let days = days.to_lowercase();
+    match days.as_str() {
+        "daily" | "everyday" => Some((0..7).collect()),
+        "weekdays" => Some((0..5).collect()),
+        "weekends" => Some(vec![5, 6]),
+        _ => DAY_NAMES
+            .iter()
+            .position(|d| days.starts_with(&d.to_lowercase()))
+            .map(|d| vec![d as u8]),
+    }
+}
+
+/// Parses a time of day (e.g. "18:00" or "24:00") into minutes since midnight.
+fn parse_time(time: &str) -> Option<u16> {
+    let (hours, minutes) = time.split_once(':')?;
+    let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    // checked before combining so that large hours can't overflow
+    (hours <= 24 && minutes < 60)
+        .then(|| hours * 60 + minutes)
+        .filter(|&total| total <= MINUTES_PER_DAY as u32)
+        .map(|total| total as u16)
+}
+
+/// Parses a comma separated list of windows such as "mon 18:00-21:00, weekends 10:00-14:00".
+pub fn parse_windows(windows: &str) -> Result<Vec<WeeklyWindow>> {
+    let mut parsed = Vec::new();
+    for window in windows.split(',').map(str::trim).filter(|w| !w.is_empty()) {
+        let parse = || {
+            let (days, times) = window.split_once(' ')?;
+            let (start, end) = times.trim().split_once('-')?;
+            let (start, end) = (parse_time(start.trim())?, parse_time(end.trim())?);
+            (start < end).then_some((parse_days(days)?, start, end))
+        };
+        let Some((days, start, end)) = parse() else {
+            bail!("Could not understand `{window}`. Use a format like `mon 18:00-21:00`.");
+        };
+        parsed.extend(days.into_iter().map(|day| WeeklyWindow { day, start, end }));
+    }
+    Ok(parsed)
+}
+
+/// Converts a schedule into sorted, merged UTC intervals within the reference week.
+pub fn utc_intervals(schedule: &Schedule, week: DateTime<Utc>) -> Vec<Interval> {
+    let Ok(tz) = schedule.timezone.parse::<Tz>() else {
+        return Vec::new();
+    };
+    let local_week = week.date_naive();
+    let mut intervals: Vec<Interval> = schedule
+        .windows
+        .iter()
+        .flat_map(|w| {
+            let local_start = (local_week + Duration::days(w.day.into())).and_time(NaiveTime::MIN)
+                + Duration::minutes(w.start.into());
+            let start = tz.from_local_datetime(&local_start).earliest()?;
+            let start = (start.with_timezone(&Utc) - week)
+                .num_minutes()
+                .rem_euclid(MINUTES_PER_WEEK);
+            let end = start + i64::from(w.end - w.start);
+            // split windows that wrap around the end of the week
+            Some(if end > MINUTES_PER_WEEK {
+                vec![(start, MINUTES_PER_WEEK), (0, end - MINUTES_PER_WEEK)]
+            } else {
+                vec![(start, end)]
+            })
+        })
+        .flatten()
+        .collect();
+    intervals.sort();
+    intervals
+        .into_iter()
+        .coalesce(|a, b| {
+            if b.0 <= a.1 {
+                Ok((a.0, a.1.max(b.1)))
+            } else {
+                Err((a, b))
+            }
+        })
+        .collect()
+}
+
+/// Returns the intersection of two sorted, merged interval lists.
+pub fn intersect(a: &[Interval], b: &[Interval]) -> Vec<Interval> {
+    let mut result = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        let (start, end) = (a[i].0.max(b[j].0), a[i].1.min(b[j].1));
+        if start < end {
+            result.push((start, end));
+        }
+        if a[i].1 < b[j].1 {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    result
+}
+
+/// Creates a score function that penalizes matching members who are never free at the same time.
+/// Members without a schedule are not penalized.
+pub fn availability_score(
+    profiles: &HashMap<UserId, Profile>,
+    week: DateTime<Utc>,
+) -> PairScore<UserId> {
+    let intervals: HashMap<UserId, Vec<Interval>> = profiles
+        .iter()
+        .flat_map(|(id, p)| Some((*id, utc_intervals(p.schedule.as_ref()?, week))))
+        .collect();
+    Box::new(move |a, b| match (intervals.get(a), intervals.get(b)) {
+        (Some(a), Some(b)) if intersect(a, b).is_empty() => -NO_OVERLAP_PENALTY,
+        _ => 0,
+    })
+}
+
+/// Formats a minute of the reference week as a day and time in `tz`.
+fn format_minute(week: DateTime<Utc>, minute: i64, tz: &Tz) -> String {
+    let time = (week + Duration::minutes(minute)).with_timezone(tz);
+    format!(
+        "{} {}",
+        DAY_NAMES[time.weekday().num_days_from_monday() as usize],
+        time.format("%H:%M")
+    )
+}
+
+/// Suggests the times at which all members of a group who have a schedule are free, formatted
+/// in the time zone of `recipient` (or UTC if they have no schedule). Returns None if fewer than
+/// two members have a schedule or they have no time in common.
+pub fn suggest_times(
+    profiles: &HashMap<UserId, Profile>,
+    week: DateTime<Utc>,
+    recipient: UserId,
+    group: &[UserId],
+) -> Option<String> {
+    let schedules: Vec<&Schedule> = group
+        .iter()
+        .flat_map(|id| profiles.get(id)?.schedule.as_ref())
+        .collect();
+    if schedules.len() < 2 {
+        return None;
+    }
+    let overlap = schedules
+        .iter()
+        .map(|s| utc_intervals(s, week))
+        .reduce(|a, b| intersect(&a, &b))?;
+    if overlap.is_empty() {
+        return None;
+    }
+    let tz: Tz = profiles
+        .get(&recipient)
+        .and_then(|p| p.schedule.as_ref())
+        .and_then(|s| s.timezone.parse().ok())
+        .unwrap_or(Tz::UTC);
+    let times = overlap
+        .iter()
+        .take(MAX_SUGGESTED_WINDOWS)
+        .map(|(start, end)| {
+            format!(
+                "{} – {}",
+                format_minute(week, *start, &tz),
+                format_minute(week, *end, &tz)
+            )
+        })
+        .join(", ");
+    Some(format!("{times} ({tz})"))
+}
+
+async fn handle_availability(
+    ctx: Context<'_>,
+    timezone: String,
+    windows: String,
+) -> Result<String> {
+    let guild_id = ctx
+        .guild_id()
+        .context("This command must be called from a guild (server).")?;
+    let Ok(tz) = timezone.trim().parse::<Tz>() else {
+        bail!(
+            "Unknown time zone `{timezone}`. Please use a name like `America/Los_Angeles` \
+            (see https://en.wikipedia.org/wiki/List_of_tz_database_time_zones)."
+        );
+    };
+    let windows = parse_windows(&windows)?;
+    let summary = windows
+        .iter()
+        .map(|w| {
+            format!(
+                "{} {:02}:{:02}-{:02}:{:02}",
+                DAY_NAMES[w.day as usize],
+                w.start / 60,
+                w.start % 60,
+                w.end / 60,
+                w.end % 60
+            )
+        })
+        .join(", ");
+    let user_id = ctx.author().id;
+    ctx.data().store.update(guild_id, |data| {
+        data.profiles.entry(user_id).or_default().schedule =
+            (!windows.is_empty()).then(|| Schedule {
+                timezone: tz.name().to_owned(),
+                windows,
+            });
+    })?;
+    Ok(if summary.is_empty() {
+        "Your availability has been cleared.".to_owned()
+    } else {
+        format!("Your availability has been saved ({tz}): {summary}")
+    })
+}
+
+/// Set your time zone and the times you're usually free each week
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_error")]
+pub async fn availability(
+    ctx: Context<'_>,
+    #[description = "Your time zone, such as America/Los_Angeles."] timezone: String,
+    #[description = "When you're free, such as \"mon 18:00-21:00, weekends 10:00-14:00\". \
+    Leave empty to clear."]
+    windows: Option<String>,
+) -> Result<()> {
+    let resp = handle_availability(ctx, timezone, windows.unwrap_or_default())
+        .instrument(command_span(ctx))
+        .await;
+    respond(ctx, resp).await
+}
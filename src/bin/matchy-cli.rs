@@ -0,0 +1,151 @@
+//! Runs the matcher offline on participant and history files, without connecting to discord.
+//! This is useful for dry runs, analysis, and running matchy meetups at in-person events.
+
+use anyhow::{bail, ensure, Context as _, Result};
+use clap::Parser;
+use itertools::Itertools;
+use matchy_meetups_bot::helpers::{checksum_matching, format_key, hash_seed, Match, Pairing};
+use matchy_meetups_bot::matching::{best_graph_pair, derive_seed, MatchingOptions};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser)]
+#[command(
+    name = "matchy-cli",
+    about = "Runs the matchy meetups matcher on local files"
+)]
+struct Args {
+    /// A file listing the participants: a JSON array of ids, or a CSV file with one id per line
+    #[arg(short, long)]
+    participants: PathBuf,
+    /// A file listing previous groups: a JSON array of arrays of ids, or a CSV file with one
+    /// comma separated group per line
+    #[arg(long)]
+    history: Option<PathBuf>,
+    /// The seed to use for the pairing (for example, the current date)
+    #[arg(short, long)]
+    seed: String,
+    /// How many candidate pairings to choose the best from
+    #[arg(short, long, default_value_t = matchy_meetups_bot::config::DEFAULT_CANDIDATES)]
+    candidates: usize,
+}
+
+/// Returns true if `path` should be read as JSON (rather than CSV).
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("json"))
+}
+
+/// Splits a CSV line into trimmed, non-empty fields.
+fn csv_fields(line: &str) -> Vec<String> {
+    line.split(',')
+        .map(|f| f.trim().trim_matches('"').to_owned())
+        .filter(|f| !f.is_empty())
+        .collect()
+}
+
+fn read_participants(path: &Path) -> Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Unable to read {}", path.display()))?;
+    if is_json(path) {
+        return serde_json::from_str(&contents)
+            .with_context(|| format!("{} should be a JSON array of ids", path.display()));
+    }
+    let mut lines: Vec<Vec<String>> = contents.lines().map(csv_fields).collect();
+    // skip a header row such as `id` above numeric (discord) ids
+    let is_id = |field: &String| field.chars().all(|c| c.is_ascii_digit());
+    if let Some((first, rest)) = lines.split_first() {
+        if !first.iter().all(is_id) && !rest.is_empty() && rest.iter().flatten().all(is_id) {
+            lines.remove(0);
+        }
+    }
+    Ok(lines.into_iter().flatten().collect())
+}
+
+fn read_history(path: &Path) -> Result<Vec<Match<String>>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Unable to read {}", path.display()))?;
+    if is_json(path) {
+        return serde_json::from_str(&contents).with_context(|| {
+            format!("{} should be a JSON array of arrays of ids", path.display())
+        });
+    }
+    Ok(contents
+        .lines()
+        .map(csv_fields)
+        .filter(|group| group.len() > 1)
+        .collect())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    ensure!(args.candidates >= 1, "--candidates must be at least 1");
+
+    let participants = read_participants(&args.participants)?;
+    if let Some(duplicate) = participants.iter().duplicates().next() {
+        bail!("Participant `{duplicate}` is listed more than once");
+    }
+    let history = match &args.history {
+        Some(path) => read_history(path)?,
+        None => Vec::new(),
+    };
+
+    // graph_pair() needs Copy elements, so match indices into `participants` instead of strings
+    let index: HashMap<&String, usize> = participants
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (p, i))
+        .collect();
+    let indexed_history: Vec<Match<usize>> = history
+        .iter()
+        .map(|m| m.iter().flat_map(|p| index.get(p)).cloned().collect())
+        .collect();
+
+    let seed = hash_seed(&args.seed);
+    let (Pairing(pairs, imperfect_matches, repeated_pairs, _), candidate, rating) =
+        best_graph_pair(
+            (0..participants.len()).collect(),
+            &indexed_history,
+            &MatchingOptions::default(),
+            seed,
+            args.candidates,
+        )?;
+
+    let name = |i: &usize| participants[*i].as_str();
+    let groups: Vec<Match<&str>> = pairs.iter().map(|m| m.iter().map(name).collect()).collect();
+    for group in &groups {
+        println!("{}", group.join(", "));
+    }
+    println!();
+    println!(
+        "Total paired members: {}",
+        groups.iter().map(|g| g.len()).sum::<usize>()
+    );
+    if imperfect_matches.is_empty() {
+        println!("All members were matched with new people");
+    } else {
+        println!(
+            "Members matched with people they may have matched with before: {}",
+            imperfect_matches.iter().map(name).join(", ")
+        );
+        println!(
+            "Repeated pairs: {repeated_pairs} (more candidates may find a pairing with fewer)"
+        );
+    }
+    println!(
+        "Chose candidate {} of {} (rating {rating})",
+        candidate + 1,
+        args.candidates
+    );
+    // the checksum covers the ids as written in the input files, so it can be used to check that
+    // two runs produced the same pairing
+    println!(
+        "Key: {}",
+        format_key(
+            &args.seed,
+            candidate,
+            &checksum_matching(derive_seed(seed, candidate), &groups)
+        )
+    );
+    Ok(())
+}
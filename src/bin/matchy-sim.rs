@@ -0,0 +1,234 @@
+//! Simulates many rounds of matchy meetups with synthetic membership (members joining, leaving
+//! and pausing), feeding each round back in as history, and reports how well each matching
+//! strategy avoids repeats. This is useful for comparing strategies before deploying them.
+
+use anyhow::{ensure, Result};
+use clap::Parser;
+use itertools::Itertools;
+use matchy_meetups_bot::helpers::{Match, Pairing};
+use matchy_meetups_bot::matching::{best_graph_pair, graph_pair, random_pair, MatchingOptions};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+#[derive(Parser)]
+#[command(
+    name = "matchy-sim",
+    about = "Compares matching strategies over many simulated rounds"
+)]
+struct Args {
+    /// The number of members at the start of the simulation
+    #[arg(short, long, default_value_t = 30)]
+    members: u32,
+    /// The number of rounds to simulate
+    #[arg(short, long, default_value_t = 52)]
+    rounds: usize,
+    /// The average number of new members joining each round
+    #[arg(long, default_value_t = 1.0)]
+    joins: f64,
+    /// The chance that a member leaves for good each round
+    #[arg(long, default_value_t = 0.02)]
+    leave_chance: f64,
+    /// The chance that a member skips a round
+    #[arg(long, default_value_t = 0.1)]
+    pause_chance: f64,
+    /// The seed for the membership changes and the matching
+    #[arg(short, long, default_value_t = 0)]
+    seed: u64,
+    /// How many candidate pairings the best_graph_pair strategy chooses from each round
+    #[arg(short, long, default_value_t = matchy_meetups_bot::config::DEFAULT_CANDIDATES)]
+    candidates: usize,
+}
+
+/// A way of creating a pairing from the participants and the history.
+type Strategy = Box<dyn Fn(Vec<u32>, &[Match<u32>], u64) -> Result<Pairing<u32>>>;
+
+/// The strategies to compare, with their names.
+fn strategies(candidates: usize) -> Vec<(String, Strategy)> {
+    vec![
+        (
+            "graph".to_owned(),
+            Box::new(|participants, history, seed| {
+                graph_pair(participants, history, &MatchingOptions::default(), seed)
+            }),
+        ),
+        (
+            format!("best_graph_pair ({candidates} candidates)"),
+            Box::new(move |participants, history, seed| {
+                let options = MatchingOptions::default();
+                best_graph_pair(participants, history, &options, seed, candidates)
+                    .map(|(pairing, ..)| pairing)
+            }),
+        ),
+        (
+            "random".to_owned(),
+            Box::new(|participants, _, seed| Ok(random_pair(participants, seed))),
+        ),
+    ]
+}
+
+/// Generates the participants of each round.
+fn simulate_membership(args: &Args) -> Vec<Vec<u32>> {
+    let mut rng = ChaCha8Rng::seed_from_u64(args.seed);
+    let mut members: Vec<u32> = (0..args.members).collect();
+    let mut next_id = args.members;
+    let mut rounds = Vec::new();
+    for _ in 0..args.rounds {
+        members.retain(|_| !rng.gen_bool(args.leave_chance));
+        // approximate a Poisson distribution with a mean of `joins` using ten trials
+        let joins = (0..10).filter(|_| rng.gen_bool(args.joins / 10.0)).count();
+        for _ in 0..joins {
+            members.push(next_id);
+            next_id += 1;
+        }
+        rounds.push(
+            members
+                .iter()
+                .cloned()
+                .filter(|_| !rng.gen_bool(args.pause_chance))
+                .collect(),
+        );
+    }
+    rounds
+}
+
+/// Returns an unordered pair of ids in a consistent order.
+fn edge(a: u32, b: u32) -> (u32, u32) {
+    (a.min(b), a.max(b))
+}
+
+/// Quality metrics for one strategy over the whole simulation.
+struct Metrics {
+    /// The first round (counting from 1) in which a pair was repeated
+    first_repeat: Option<usize>,
+    pairs: usize,
+    repeated_pairs: usize,
+    /// How many members have been in a triple the given number of times
+    triple_counts: BTreeMap<usize, usize>,
+    /// The number of distinct pairs that have met
+    pairs_met: usize,
+    /// The number of distinct pairs that were ever in the same round, and so could have met
+    possible_pairs: usize,
+}
+
+fn run_strategy(strategy: &Strategy, rounds: &[Vec<u32>], seed: u64) -> Result<Metrics> {
+    let mut history: Vec<Match<u32>> = Vec::new();
+    let mut met: HashSet<(u32, u32)> = HashSet::new();
+    let mut possible: HashSet<(u32, u32)> = HashSet::new();
+    let mut triples: HashMap<u32, usize> = HashMap::new();
+    let mut first_repeat = None;
+    let (mut pairs, mut repeated_pairs) = (0, 0);
+
+    for (round, participants) in rounds.iter().enumerate() {
+        if participants.len() < 2 {
+            continue;
+        }
+        for &member in participants {
+            triples.entry(member).or_default();
+        }
+        possible.extend(
+            participants
+                .iter()
+                .tuple_combinations()
+                .map(|(&a, &b)| edge(a, b)),
+        );
+
+        let Pairing(groups, ..) = strategy(
+            participants.clone(),
+            &history,
+            seed.wrapping_add(round as u64),
+        )?;
+        for group in &groups {
+            if group.len() > 2 {
+                for member in group {
+                    *triples.entry(*member).or_default() += 1;
+                }
+            }
+            for (&a, &b) in group.iter().tuple_combinations() {
+                pairs += 1;
+                if !met.insert(edge(a, b)) {
+                    repeated_pairs += 1;
+                    first_repeat.get_or_insert(round + 1);
+                }
+            }
+        }
+        history.extend(groups);
+    }
+
+    Ok(Metrics {
+        first_repeat,
+        pairs,
+        repeated_pairs,
+        triple_counts: triples.values().cloned().counts().into_iter().collect(),
+        pairs_met: met.len(),
+        possible_pairs: possible.len(),
+    })
+}
+
+fn percent(numerator: usize, denominator: usize) -> String {
+    if denominator == 0 {
+        return "-".to_owned();
+    }
+    format!("{:.1}%", 100.0 * numerator as f64 / denominator as f64)
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    for (name, chance) in [
+        ("--leave-chance", args.leave_chance),
+        ("--pause-chance", args.pause_chance),
+    ] {
+        ensure!(
+            (0.0..=1.0).contains(&chance),
+            "{name} must be between 0 and 1"
+        );
+    }
+    ensure!(
+        (0.0..=10.0).contains(&args.joins),
+        "--joins must be between 0 and 10"
+    );
+    ensure!(args.candidates >= 1, "--candidates must be at least 1");
+    let rounds = simulate_membership(&args);
+
+    let everyone: HashSet<u32> = rounds.iter().flatten().cloned().collect();
+    println!(
+        "Simulated {} rounds with {} members in total ({} in the first round, {} in the last)",
+        rounds.len(),
+        everyone.len(),
+        rounds.first().map_or(0, Vec::len),
+        rounds.last().map_or(0, Vec::len),
+    );
+
+    for (name, strategy) in strategies(args.candidates) {
+        let metrics = run_strategy(&strategy, &rounds, args.seed)?;
+        println!();
+        println!("Strategy: {name}");
+        println!(
+            "  First repeat: {}",
+            metrics
+                .first_repeat
+                .map_or("never".to_owned(), |r| format!("round {r}"))
+        );
+        println!(
+            "  Repeat rate: {} ({} of {} pairs)",
+            percent(metrics.repeated_pairs, metrics.pairs),
+            metrics.repeated_pairs,
+            metrics.pairs
+        );
+        println!(
+            "  Members by number of triples: {}",
+            metrics
+                .triple_counts
+                .iter()
+                .map(|(triples, members)| format!("{triples}×: {members}"))
+                .join(", ")
+        );
+        println!(
+            "  Coverage: {} ({} of {} possible pairs met)",
+            percent(metrics.pairs_met, metrics.possible_pairs),
+            metrics.pairs_met,
+            metrics.possible_pairs
+        );
+    }
+    Ok(())
+}
@@ -1,3 +1,39 @@
 // todo: allow users to configure
 pub const HISTORY_CHANNEL_NAME: &str = "matchy-meetups-history";
 pub const NOTIFICATION_CHANNEL_NAME: &str = "matchy-meetups";
+
+/// The environment variable containing the path of the JSON file used to store guild data.
+pub const DATA_PATH_ENV_VAR: &str = "MATCHY_DATA_PATH";
+pub const DEFAULT_DATA_PATH: &str = "matchy_data.json";
+
+/// The environment variable that sets the port of the HTTP server for `/metrics`, `/healthz` and
+/// `/readyz`
+pub const HTTP_PORT_ENV_VAR: &str = "MATCHY_HTTP_PORT";
+pub const DEFAULT_HTTP_PORT: u16 = 9000;
+
+/// The environment variable that sets which logs are written, such as `debug`
+pub const LOG_ENV_VAR: &str = "MATCHY_LOG";
+/// The environment variable which, when set to `json`, writes logs as JSON lines
+pub const LOG_FORMAT_ENV_VAR: &str = "MATCHY_LOG_FORMAT";
+
+/// The environment variable which, when set to `true`, enables the privileged GUILD_MEMBERS intent
+/// so members can be looked up from an in-memory index instead of the REST API. The intent must
+/// also be enabled for the bot in the discord developer portal.
+pub const MEMBER_INTENT_ENV_VAR: &str = "MATCHY_GUILD_MEMBERS_INTENT";
+
+/// The default number of candidate pairings /create_pairing chooses between
+pub const DEFAULT_CANDIDATES: usize = 10;
+/// The maximum number of candidate pairings /create_pairing can choose between
+pub const MAX_CANDIDATES: usize = 50;
+/// The longest seed /create_pairing accepts, in characters. The seed is part of the key, which is
+/// recorded in every history message of a round, so it must leave room for the groups.
+pub const MAX_SEED_LENGTH: usize = 100;
+
+/// The maximum weight of an attribute. Each repeated pair costs a pairing 1000 in rate_pairing(),
+/// so a matching attribute stays far cheaper than a repeat.
+pub const MAX_ATTRIBUTE_WEIGHT: i64 = 10;
+
+/// The maximum weight of an extra history source
+pub const MAX_HISTORY_WEIGHT: u32 = 10;
+/// The largest history file that can be uploaded, in bytes
+pub const MAX_HISTORY_FILE_SIZE: u32 = 1024 * 1024;
@@ -1,32 +1,199 @@
-use crate::discord_helpers::match_members;
-use crate::helpers::{format_id, format_pairs, hash_seed};
-use crate::helpers::{handle_error, Pairing};
-use crate::types::Context;
-use anyhow::Result;
+use crate::audit::{audited, set_audit_outcome};
+use crate::config::{DEFAULT_CANDIDATES, MAX_CANDIDATES};
+use crate::discord_helpers::{match_members, Candidates, MemberMatching};
+use crate::guild_api::{GuildApi, SerenityGuild};
+use crate::helpers::{
+    checksum_matching, format_id, format_key, format_pairs, hash_seed, split_message, validate_seed,
+};
+use crate::helpers::{command_span, handle_error, MatchDetails, Pairing, PastMatch};
+use crate::matching::derive_seed;
+use crate::permissions::{author_has_level, require_view};
+use crate::program::{autocomplete_program, Program, DEFAULT_PROGRAM};
+use crate::storage::PermissionLevel;
+use crate::types::{Context, Data};
+use anyhow::{ensure, Result};
 use itertools::Itertools;
+use serenity::all::{Timestamp, UserId};
+use tracing::{debug, Instrument, Span};
 
-async fn handle_create_pairing(ctx: Context<'_>, seed_str: String) -> Result<String> {
+/// Explains how each Match in a pairing was made: when its members last met, which constraints
+/// were relaxed, and its score.
+fn explain_pairing(
+    pairs: &[Vec<UserId>],
+    details: &[MatchDetails<UserId>],
+    history: &[PastMatch],
+) -> String {
+    let last_met = |a: &UserId, b: &UserId| {
+        history
+            .iter()
+            .filter(|m| m.members.contains(a) && m.members.contains(b))
+            .max_by_key(|m| m.timestamp)
+    };
+    pairs
+        .iter()
+        .zip(details)
+        .enumerate()
+        .map(|(i, (pair, d))| {
+            let mut lines = vec![format!(
+                "**{}.** {} (score {})",
+                i + 1,
+                pair.iter().map(format_id).join(", "),
+                d.score
+            )];
+            if d.repeated.is_empty() {
+                lines.push("- None of these members have met before".to_owned());
+            }
+            for (a, b) in &d.repeated {
+                let when = last_met(a, b)
+                    .map(|m| {
+                        format!(
+                            "<t:{}:R> (history message `{}`)",
+                            m.timestamp.unix_timestamp(),
+                            m.message_id
+                        )
+                    })
+                    .unwrap_or_else(|| "before".to_owned());
+                lines.push(format!(
+                    "- Relaxed history: {} and {} last met {when}",
+                    format_id(a),
+                    format_id(b)
+                ));
+            }
+            if d.preferred {
+                lines.push("- Matched because of a prefer-match rule".to_owned());
+            }
+            if let Some(remainder) = d.remainder {
+                lines.push(format!(
+                    "- {} was added as a third member because there was an odd number of members",
+                    format_id(&remainder)
+                ));
+            }
+            lines.join("\n")
+        })
+        .join("\n")
+}
+
+/// The result of /create_pairing.
+#[derive(Debug)]
+pub struct CreatedPairing {
+    /// A description of the pairing, without the key
+    pub response: String,
+    /// An explanation of the pairing, if requested
+    pub explanation: Option<String>,
+    /// The key to send the pairing with
+    pub key: String,
+}
+
+impl CreatedPairing {
+    /// The reply to the organizer. It includes the key only if they can create pairings that can
+    /// be sent; otherwise the pairing is a draft.
+    pub fn reply(&self, can_create: bool) -> String {
+        if can_create {
+            format!(
+                "{}\nTo send this pairing, use this key: `{}`",
+                self.response, self.key
+            )
+        } else {
+            format!(
+                "{}\nThis is a draft. Creating a pairing that can be sent needs the `create` \
+                permission.",
+                self.response
+            )
+        }
+    }
+}
+
+/// Run the /create_pairing command.
+pub async fn handle_create_pairing(
+    api: &impl GuildApi,
+    data: &Data,
+    program: &str,
+    seed_str: String,
+    candidates: usize,
+    explain: bool,
+) -> Result<CreatedPairing> {
+    Span::current()
+        .record("program", program)
+        .record("seed", seed_str.as_str());
+    validate_seed(&seed_str)?;
     let seed = hash_seed(&seed_str);
 
-    let Pairing(pairs, imperfect_matches) = match_members(ctx, seed).await?;
+    ensure!(
+        (1..=MAX_CANDIDATES).contains(&candidates),
+        "The number of candidates must be between 1 and {MAX_CANDIDATES}."
+    );
+    let program = Program::load(api, data, program)?;
+    let MemberMatching {
+        pairing: Pairing(pairs, imperfect_matches, repeated_pairs, details),
+        candidate,
+        rating,
+        history,
+        excluded,
+        edited_history_messages,
+        ..
+    } = match_members(api, data, &program, &seed_str, Candidates::Best(candidates)).await?;
     let pairs_str = format_pairs(&pairs);
-    let key = format!(
-        "{}_{}",
-        seed_str,
-        crate::helpers::checksum_matching(seed, &pairs)
+    let key = format_key(
+        &seed_str,
+        candidate,
+        &checksum_matching(derive_seed(seed, candidate), &pairs),
     );
     let num_members: usize = pairs.iter().map(|p| p.len()).sum();
     let imperfect_matches_message = if imperfect_matches.is_empty() {
         "All members were matched with new people".to_owned()
     } else {
         format!(
-            "The following members could only be matched with people they may have matched with before: {}",
+            "The following members could only be matched with people they may have matched with before: {}\n\
+            Repeated pairs: {repeated_pairs} (more candidates may find a pairing with fewer)",
             imperfect_matches.iter().map(format_id).join(", ")
         )
     };
-    Ok(format!(
-        "{pairs_str}\nTotal paired members: {num_members}\n{imperfect_matches_message}\nTo send this pairing, use this key: `{key}`"
-    ))
+    let excluded_message = if excluded.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "Excluded members: {}\n",
+            excluded
+                .iter()
+                .map(|(id, reason)| format!("{} ({reason})", format_id(id)))
+                .join(", ")
+        )
+    };
+    let edited_message = if edited_history_messages.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "These history messages were edited after they were posted, so they were not used: {}\n",
+            edited_history_messages.join(" ")
+        )
+    };
+    let cadence_days = program.data.settings.cadence_days;
+    let cadence_message = match history.iter().map(|m| m.timestamp).max() {
+        Some(last_round) => {
+            let days_since =
+                (Timestamp::now().unix_timestamp() - last_round.unix_timestamp()) / (24 * 60 * 60);
+            if days_since < cadence_days.into() {
+                format!(
+                    "The last round of `{}` was sent {days_since} day(s) ago, but it runs every \
+                    {cadence_days} days.\n",
+                    program.name
+                )
+            } else {
+                String::new()
+            }
+        }
+        None => String::new(),
+    };
+    let explanation = explain.then(|| explain_pairing(&pairs, &details, &history));
+    Ok(CreatedPairing {
+        response: format!(
+            "{pairs_str}\nTotal paired members: {num_members}\n{imperfect_matches_message}\n\
+            {excluded_message}{edited_message}{cadence_message}Chose candidate {} of {candidates} (rating {rating})",
+            candidate + 1
+        ),
+        explanation,
+        key,
+    })
 }
 
 /// Generate a potential pairing of users who have reacted to a message
@@ -35,7 +202,8 @@ async fn handle_create_pairing(ctx: Context<'_>, seed_str: String) -> Result<Str
     track_edits,
     hide_in_help,
     ephemeral,
-    required_permissions = "ADMINISTRATOR",
+    check = "require_view",
+    check = "audited",
     aliases("pair"),
     on_error = "handle_error"
 )]
@@ -43,12 +211,46 @@ pub async fn create_pairing(
     ctx: Context<'_>,
     #[description = "A seed to use for the generated pairing (for example, use the current date)."]
     seed: String,
+    #[description = "How many candidate pairings to choose the best from (default 10)."]
+    candidates: Option<usize>,
+    #[description = "Explain why each group was chosen."] explain: Option<bool>,
+    #[description = "The program to pair (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
 ) -> Result<()> {
     ctx.defer_ephemeral().await?;
-    let resp = handle_create_pairing(ctx, seed)
-        .await
-        .unwrap_or_else(|e| format!("Error: {}", e));
-    println!("{resp}");
+    let span = command_span(ctx);
+    let program = program.unwrap_or_else(|| DEFAULT_PROGRAM.to_owned());
+    let candidates = candidates.unwrap_or(DEFAULT_CANDIDATES);
+    let explain = explain.unwrap_or(false);
+    let result = async {
+        let api = SerenityGuild::new(ctx)?;
+        // organizers who can only view get a draft, without the key needed to send it
+        let can_create = author_has_level(ctx, PermissionLevel::Create).await?;
+        let result =
+            handle_create_pairing(&api, ctx.data(), &program, seed, candidates, explain).await;
+        let outcome = match &result {
+            Ok(created) if can_create => format!("Created a pairing with key `{}`", created.key),
+            Ok(_) => "Created a draft pairing without a key".to_owned(),
+            Err(e) => format!("Error: {e}"),
+        };
+        set_audit_outcome(ctx, outcome).await;
+        result.map(|created| (created, can_create))
+    }
+    .instrument(span.clone())
+    .await;
+    let (resp, explanation) = match result {
+        Ok((created, can_create)) => (created.reply(can_create), created.explanation),
+        Err(e) => (format!("Error: {}", e), None),
+    };
+    span.in_scope(|| debug!("{resp}"));
     ctx.say(resp).await?;
+    for chunk in explanation
+        .as_deref()
+        .map(split_message)
+        .unwrap_or_default()
+    {
+        ctx.say(chunk).await?;
+    }
     Ok(())
 }
@@ -1,134 +1,234 @@
-use crate::config::HISTORY_CHANNEL_NAME;
-use crate::helpers::{Match, Pairing};
-use crate::matching::graph_pair;
-use crate::types::Context;
-use crate::ROLE_NAME;
-use anyhow::{bail, Context as _, Result};
-use chrono::{Duration, Local};
+use crate::attributes::attribute_score;
+use crate::availability::{availability_score, reference_week};
+use crate::guild_api::{GuildApi, GuildMember};
+use crate::helpers::{hash_seed, Match, Pairing, PastMatch};
+use crate::history::{HistoryAuthors, PastHistory};
+use crate::matching::{
+    best_graph_pair, derive_seed, graph_pair, rate_pairing, MatchingOptions, Mentorship,
+};
+use crate::profile::interest_score;
+use crate::program::Program;
+use crate::storage::{HistorySourceKind, Lookback};
+use crate::types::Data;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, Utc};
 use itertools::Itertools;
-use poise::futures_util::StreamExt;
-use regex::Regex;
-use serenity::all::{ChannelId, Guild, GuildChannel, GuildId, RoleId, UserId};
+use serenity::all::{RoleId, UserId};
 
-pub async fn find_channel(
-    ctx: &Context<'_>,
-    guild_id: GuildId,
-    name: &str,
-) -> Result<Option<GuildChannel>> {
-    Ok(guild_id
-        .channels(ctx)
-        .await?
-        .into_iter()
-        .find(|(_, c)| c.name == name)
-        .map(|(_, c)| c))
+/// Why a member with the matchy meetups role was not matched.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Exclusion {
+    Bot,
+    /// The member has not passed the guild's membership screening yet
+    Pending,
+    /// The member joined fewer than this many days ago
+    JoinedRecently(u32),
 }
 
-/// Returns a vector of all guild members with the specified role ID.
-async fn guild_members_with_role(
-    ctx: &Context<'_>,
-    guild: &Guild,
-    role_id: RoleId,
-) -> anyhow::Result<Vec<UserId>> {
-    // max number of pages to try to fetch (to avoid infinite loops in the event of the server
-    // response format changing in a way that breaks the end-of-page detection)
-    const MAX_PAGES: u64 = 20;
-
-    // maximum number of members to request per page
-    const PAGE_LIMIT: u64 = 1000;
-
-    let mut last_member = None;
-    let mut members_with_role = Vec::new();
-
-    for _ in 0..MAX_PAGES {
-        let page = guild.members(&ctx, Some(PAGE_LIMIT), last_member).await?;
-
-        members_with_role.extend(
-            page.iter()
-                .filter(move |u| u.roles.iter().contains(&role_id))
-                .map(|p| p.user.id),
-        );
-
-        if page.len() < PAGE_LIMIT as usize {
-            break;
+impl std::fmt::Display for Exclusion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Exclusion::Bot => write!(f, "bot account"),
+            Exclusion::Pending => write!(f, "has not passed membership screening"),
+            Exclusion::JoinedRecently(days) => {
+                write!(
+                    f,
+                    "joined less than {days} day{} ago",
+                    if *days == 1 { "" } else { "s" }
+                )
+            }
         }
-        last_member = Some(
-            page.last()
-                .expect("page is never empty here if PAGE_LIMIT > 0")
-                .user
-                .id,
-        );
     }
-
-    Ok(members_with_role)
 }
 
-/// Returns an iterable over all previous pairings
-pub async fn previous_matches(
-    ctx: &Context<'_>,
-    channel_id: ChannelId,
-) -> anyhow::Result<Vec<Match<UserId>>> {
-    const MAX_MESSAGES_TO_REQUEST: usize = 1000;
-
-    let mut pairings: Vec<Vec<UserId>> = Vec::new();
-
-    let re = Regex::new(r"<@([0-9]+)>").expect("regex creation should succeed");
-
-    let mut messages = channel_id
-        .messages_iter(&ctx)
-        .boxed()
-        .take(MAX_MESSAGES_TO_REQUEST);
-
-    while let Some(message_result) = messages.next().await {
-        match message_result {
-            Ok(message) => {
-                if *message.timestamp < Local::now() - Duration::days(365) {
-                    // this message was not within the last year
-                    break;
-                }
-                pairings.extend(
-                    message
-                        .content
-                        .split("\n")
-                        .map(|line| {
-                            re.captures_iter(line)
-                                .map(|c| c.extract())
-                                .flat_map(|(_, [id])| id.parse().ok())
-                                .collect()
-                        })
-                        .filter(|pair: &Vec<_>| pair.len() > 1),
-                );
-            }
-            Err(error) => bail!("Error fetching message history: {}", error),
+/// Splits members into those who can be matched and those who are excluded (with the reason).
+/// Members who appear more than once (for example, because of overlapping pages) are only
+/// included once.
+pub fn eligible_members(
+    members: Vec<GuildMember>,
+    min_member_days: u32,
+) -> (Vec<GuildMember>, Vec<(UserId, Exclusion)>) {
+    let joined_cutoff = Utc::now() - Duration::days(min_member_days.into());
+    let mut eligible = Vec::new();
+    let mut excluded = Vec::new();
+    for member in members.into_iter().unique_by(|m| m.id) {
+        let exclusion = if member.bot {
+            Some(Exclusion::Bot)
+        } else if member.pending {
+            Some(Exclusion::Pending)
+        } else if member.joined_at.is_some_and(|t| *t > joined_cutoff) {
+            Some(Exclusion::JoinedRecently(min_member_days))
+        } else {
+            None
+        };
+        match exclusion {
+            Some(exclusion) => excluded.push((member.id, exclusion)),
+            None => eligible.push(member),
         }
     }
-    Ok(pairings)
+    (eligible, excluded)
 }
 
-/// Pairs members with ROLE_NAME in the guild together.
-/// The result is a pairing of
-pub async fn match_members(ctx: Context<'_>, seed: u64) -> Result<Pairing<UserId>> {
-    let guild = ctx
-        .guild()
-        .context("This command must be called from a guild (server).")?
-        .clone();
-    let Some(role) = guild.role_by_name(ROLE_NAME) else {
-        bail!("Could not find a role with name `{ROLE_NAME}`");
-    };
-    let Some(history_channel) = find_channel(&ctx, guild.id, HISTORY_CHANNEL_NAME).await? else {
-        bail!("Could not find history channel");
-    };
-    let participants = guild_members_with_role(&ctx, &guild, role.id).await?;
+/// Which candidate pairings match_members() should consider.
+pub enum Candidates {
+    /// Choose the best of this many candidates
+    Best(usize),
+    /// Use exactly this candidate (for reproducing a pairing from its key)
+    Exactly(usize),
+}
+
+/// The result of match_members().
+pub struct MemberMatching {
+    pub pairing: Pairing<UserId>,
+    /// The index of the candidate that produced the pairing
+    pub candidate: usize,
+    /// The rating of the pairing (higher is better)
+    pub rating: i64,
+    /// The previous matches from the history channel (not including the extra history sources)
+    pub history: Vec<PastMatch>,
+    /// The week members' schedules were compared in, which suggested times should use too
+    pub week: DateTime<Utc>,
+    /// Members with the role who were not matched, and why
+    pub excluded: Vec<(UserId, Exclusion)>,
+    /// Links to history messages that were ignored because they were edited after being posted
+    pub edited_history_messages: Vec<String>,
+}
+
+/// Pairs the members of a program together.
+pub async fn match_members(
+    api: &impl GuildApi,
+    data: &Data,
+    program: &Program,
+    seed_str: &str,
+    candidates: Candidates,
+) -> Result<MemberMatching> {
+    let seed = hash_seed(seed_str);
+    let role_id = program.role(api)?;
+    let history_channel = program.history_channel(api).await?;
+    let settings = &program.data.settings;
+    let mut role_members = api.members_with_role(role_id).await?;
+    // in a mentorship program, the mentors don't need the program's role
+    if let Some(mentorship) = settings.mentorship {
+        role_members.extend(api.members_with_role(mentorship.mentor_role).await?);
+    }
+    let (members, excluded) = eligible_members(role_members, settings.min_member_days);
+    let members: Vec<(UserId, Vec<RoleId>)> =
+        members.into_iter().map(|m| (m.id, m.roles)).collect();
+    let participants: Vec<UserId> = members.iter().map(|(id, _)| *id).collect();
     if participants.len() <= 1 {
         bail!(
-            "Need at least two members to create a pairing (found {} member{} with role <@&{}>).",
+            "Need at least two members to create a pairing (found {} eligible member{} with role \
+            <@&{}>, and excluded {}).",
             participants.len(),
             if participants.len() == 1 { "" } else { "s" },
-            role.id
+            role_id,
+            excluded.len()
         );
     }
-    Ok(graph_pair(
-        participants,
-        &previous_matches(&ctx, history_channel.id).await?,
-        seed,
-    )?)
+    let PastHistory {
+        matches: history,
+        edited_messages,
+    } = data
+        .history_cache
+        .previous_matches(
+            api,
+            history_channel,
+            HistoryAuthors::Bot,
+            settings.history_lookback,
+        )
+        .await?;
+    let week = reference_week(seed_str, history.iter().map(|m| m.timestamp).max());
+    let mut options = {
+        let program = &program.data;
+        let mut pair_scores = Vec::new();
+        if !program.attributes.is_empty() {
+            pair_scores.push(attribute_score(program.attributes.clone(), &members));
+        }
+        data.store.read(api.guild_id(), |data| {
+            if !data.profiles.is_empty() {
+                pair_scores.push(interest_score(&data.profiles));
+                pair_scores.push(availability_score(&data.profiles, week));
+            }
+        });
+        MatchingOptions {
+            never_match: program.never_match.clone(),
+            prefer_match: program.prefer_match.clone(),
+            pair_scores,
+            mentorship: settings.mentorship.map(|mentorship| Mentorship {
+                mentors: members
+                    .iter()
+                    .filter(|(_, roles)| roles.contains(&mentorship.mentor_role))
+                    .map(|(id, _)| *id)
+                    .collect(),
+                capacity: mentorship.mentees_per_mentor as usize,
+            }),
+            other_history: Vec::new(),
+        }
+    };
+    let mut edited_history_messages: Vec<String> = edited_messages
+        .iter()
+        .map(|id| id.link(history_channel, Some(api.guild_id())))
+        .collect();
+    // only the program's own rounds count towards previous triples
+    let previous_pairings: Vec<Match<UserId>> = history.iter().map(|m| m.members.clone()).collect();
+    for source in &program.data.history_sources {
+        let groups = match &source.kind {
+            HistorySourceKind::Channel(channel) => {
+                let PastHistory {
+                    matches,
+                    edited_messages,
+                } = data
+                    .history_cache
+                    .previous_matches(api, *channel, HistoryAuthors::Anyone, source.lookback)
+                    .await?;
+                edited_history_messages.extend(
+                    edited_messages
+                        .iter()
+                        .map(|id| id.link(*channel, Some(api.guild_id()))),
+                );
+                matches.into_iter().map(|m| m.members).collect()
+            }
+            // a file is a single round, so it is only left out once it is older than the lookback
+            HistorySourceKind::File { uploaded, groups } => match source.lookback {
+                Lookback::Days(days) if **uploaded < Utc::now() - Duration::days(days.into()) => {
+                    Vec::new()
+                }
+                _ => groups.clone(),
+            },
+        };
+        // a source with a higher weight counts as meeting that many times, so its pairs are the
+        // last to be repeated
+        for group in groups {
+            options
+                .other_history
+                .extend(itertools::repeat_n(group, source.weight as usize));
+        }
+    }
+    let timer = data.metrics.matching_duration.start_timer();
+    // matching a large program can take long enough to hold up other commands, so it runs on a
+    // blocking thread rather than the async executor
+    let (pairing, candidate, rating) = tokio::task::spawn_blocking(move || match candidates {
+        Candidates::Best(n) => best_graph_pair(participants, &previous_pairings, &options, seed, n),
+        Candidates::Exactly(candidate) => {
+            let pairing = graph_pair(
+                participants,
+                &previous_pairings,
+                &options,
+                derive_seed(seed, candidate),
+            )?;
+            let rating = rate_pairing(&pairing, &previous_pairings);
+            Ok((pairing, candidate, rating))
+        }
+    })
+    .await??;
+    timer.observe_duration();
+    Ok(MemberMatching {
+        pairing,
+        candidate,
+        rating,
+        history,
+        week,
+        excluded,
+        edited_history_messages,
+    })
 }
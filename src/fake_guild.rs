@@ -0,0 +1,201 @@
+use crate::guild_api::{ChannelMessage, GuildApi, GuildMember, PostedMessage};
+use anyhow::{bail, Context as _, Result};
+use serenity::all::{ChannelId, GuildId, MessageId, RoleId, Timestamp, UserId};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// An in-memory GuildApi, used to test the command logic without connecting to discord.
+pub struct FakeGuild {
+    pub id: GuildId,
+    /// The ID of the bot's own user, used as the author of posted messages
+    pub bot_id: UserId,
+    pub roles: HashMap<String, RoleId>,
+    /// Members in the order they are returned by the API, with their names
+    pub members: Vec<(GuildMember, String)>,
+    pub channels: HashMap<String, ChannelId>,
+    /// Messages in each channel, oldest first
+    pub messages: Mutex<HashMap<ChannelId, Vec<ChannelMessage>>>,
+    /// Direct messages sent to each user, in order
+    pub dms: Mutex<Vec<(UserId, String)>>,
+    next_id: Mutex<u64>,
+}
+
+impl FakeGuild {
+    pub fn new(id: u64) -> Self {
+        FakeGuild {
+            id: GuildId::new(id),
+            bot_id: UserId::new(1),
+            roles: HashMap::new(),
+            members: Vec::new(),
+            channels: HashMap::new(),
+            messages: Mutex::new(HashMap::new()),
+            dms: Mutex::new(Vec::new()),
+            next_id: Mutex::new(1_000_000),
+        }
+    }
+
+    /// Adds a role, returning its ID.
+    pub fn add_role(&mut self, name: &str) -> RoleId {
+        let id = RoleId::new(self.new_id());
+        self.roles.insert(name.to_owned(), id);
+        id
+    }
+
+    /// Adds a channel, returning its ID.
+    pub fn add_channel(&mut self, name: &str) -> ChannelId {
+        let id = ChannelId::new(self.new_id());
+        self.channels.insert(name.to_owned(), id);
+        id
+    }
+
+    /// Adds a member with the given roles, returning their ID.
+    pub fn add_member(&mut self, name: &str, roles: &[RoleId]) -> UserId {
+        let id = UserId::new(self.new_id());
+        let member = GuildMember {
+            id,
+            roles: roles.to_vec(),
+            bot: false,
+            pending: false,
+            joined_at: None,
+        };
+        self.members.push((member, name.to_owned()));
+        id
+    }
+
+    /// Returns a mutable reference to a member, to change their details.
+    pub fn member_mut(&mut self, user_id: UserId) -> &mut GuildMember {
+        &mut self
+            .members
+            .iter_mut()
+            .find(|(m, _)| m.id == user_id)
+            .expect("member should exist")
+            .0
+    }
+
+    /// Adds a message to a channel as if it was posted by `author`, returning its ID.
+    pub fn add_message(&self, channel_id: ChannelId, author: UserId, content: &str) -> MessageId {
+        let id = MessageId::new(self.new_id());
+        self.messages
+            .lock()
+            .expect("lock should not be poisoned")
+            .entry(channel_id)
+            .or_default()
+            .push(ChannelMessage {
+                id,
+                author,
+                content: content.to_owned(),
+                timestamp: Timestamp::now(),
+                edited_timestamp: None,
+            });
+        id
+    }
+
+    /// Returns the contents of the messages in a channel, oldest first.
+    pub fn channel_contents(&self, channel_id: ChannelId) -> Vec<String> {
+        self.messages
+            .lock()
+            .expect("lock should not be poisoned")
+            .get(&channel_id)
+            .map(|m| m.iter().map(|m| m.content.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    fn new_id(&self) -> u64 {
+        let mut next_id = self.next_id.lock().expect("lock should not be poisoned");
+        *next_id += 1;
+        *next_id
+    }
+}
+
+impl GuildApi for FakeGuild {
+    fn guild_id(&self) -> GuildId {
+        self.id
+    }
+
+    fn bot_id(&self) -> UserId {
+        self.bot_id
+    }
+
+    fn role_by_name(&self, name: &str) -> Option<RoleId> {
+        self.roles.get(name).cloned()
+    }
+
+    async fn members_with_role(&self, role_id: RoleId) -> Result<Vec<GuildMember>> {
+        Ok(self
+            .members
+            .iter()
+            .filter(|(m, _)| m.roles.contains(&role_id))
+            .map(|(m, _)| m.clone())
+            .collect())
+    }
+
+    async fn find_channel(&self, name: &str) -> Result<Option<ChannelId>> {
+        Ok(self.channels.get(name).cloned())
+    }
+
+    async fn messages_before(
+        &self,
+        channel_id: ChannelId,
+        before: Option<MessageId>,
+        limit: u8,
+    ) -> Result<Vec<ChannelMessage>> {
+        let messages = self.messages.lock().expect("lock should not be poisoned");
+        Ok(messages
+            .get(&channel_id)
+            .map(|m| {
+                m.iter()
+                    .rev()
+                    .filter(|m| match before {
+                        Some(before) => m.id < before,
+                        None => true,
+                    })
+                    .take(limit.into())
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn post(&self, channel_id: ChannelId, content: String) -> Result<PostedMessage> {
+        if !self.channels.values().any(|c| *c == channel_id) {
+            bail!("Unknown channel {channel_id}");
+        }
+        let id = self.add_message(channel_id, self.bot_id, &content);
+        Ok(PostedMessage {
+            id,
+            link: format!("https://discord.com/channels/{}/{channel_id}/{id}", self.id),
+        })
+    }
+
+    async fn edit(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        content: String,
+    ) -> Result<()> {
+        let mut messages = self.messages.lock().expect("lock should not be poisoned");
+        let message = messages
+            .get_mut(&channel_id)
+            .and_then(|m| m.iter_mut().find(|m| m.id == message_id))
+            .context("Unknown message")?;
+        message.content = content;
+        message.edited_timestamp = Some(Timestamp::now());
+        Ok(())
+    }
+
+    async fn user_name(&self, user_id: UserId) -> Result<String> {
+        self.members
+            .iter()
+            .find(|(m, _)| m.id == user_id)
+            .map(|(_, name)| name.clone())
+            .context("Unknown user")
+    }
+
+    async fn dm(&self, user_id: UserId, content: String) -> Result<()> {
+        self.dms
+            .lock()
+            .expect("lock should not be poisoned")
+            .push((user_id, content));
+        Ok(())
+    }
+}
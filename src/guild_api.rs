@@ -0,0 +1,253 @@
+use crate::types::Context;
+use anyhow::{Context as _, Result};
+use itertools::Itertools;
+use serenity::all::{
+    ChannelId, EditMessage, GetMessages, Guild, GuildId, Member, MessageId, RoleId, Timestamp,
+    UserId,
+};
+use std::future::Future;
+
+/// The maximum number of messages that can be requested in one page
+pub const MESSAGE_PAGE_LIMIT: u8 = 100;
+
+/// A message in a guild channel.
+#[derive(Clone, Debug)]
+pub struct ChannelMessage {
+    pub id: MessageId,
+    pub author: UserId,
+    pub content: String,
+    pub timestamp: Timestamp,
+    /// When the message was last edited, if it has been
+    pub edited_timestamp: Option<Timestamp>,
+}
+
+/// A member of a guild.
+#[derive(Clone, Debug)]
+pub struct GuildMember {
+    pub id: UserId,
+    pub roles: Vec<RoleId>,
+    /// Whether the member is a bot account
+    pub bot: bool,
+    /// Whether the member has not yet passed the guild's membership screening
+    pub pending: bool,
+    /// When the member joined the guild, if known
+    pub joined_at: Option<Timestamp>,
+}
+
+impl From<&Member> for GuildMember {
+    fn from(member: &Member) -> Self {
+        GuildMember {
+            id: member.user.id,
+            roles: member.roles.clone(),
+            bot: member.user.bot,
+            pending: member.pending,
+            joined_at: member.joined_at,
+        }
+    }
+}
+
+/// A message posted by the bot.
+#[derive(Clone, Debug)]
+pub struct PostedMessage {
+    pub id: MessageId,
+    /// A link to the message
+    pub link: String,
+}
+
+/// The parts of a discord guild (server) used by the matchy meetups commands. This allows the
+/// command logic to be run against an in-memory guild in tests.
+pub trait GuildApi: Sync {
+    fn guild_id(&self) -> GuildId;
+
+    /// Returns the user ID of the bot itself.
+    fn bot_id(&self) -> UserId;
+
+    /// Returns the ID of the role with the given name.
+    fn role_by_name(&self, name: &str) -> Option<RoleId>;
+
+    /// Returns all guild members with the specified role ID.
+    fn members_with_role(
+        &self,
+        role_id: RoleId,
+    ) -> impl Future<Output = Result<Vec<GuildMember>>> + Send;
+
+    /// Returns the ID of the channel with the given name.
+    fn find_channel(&self, name: &str) -> impl Future<Output = Result<Option<ChannelId>>> + Send;
+
+    /// Returns up to `limit` messages from the channel, newest first, that were posted before
+    /// `before` (or the newest messages if `before` is None).
+    fn messages_before(
+        &self,
+        channel_id: ChannelId,
+        before: Option<MessageId>,
+        limit: u8,
+    ) -> impl Future<Output = Result<Vec<ChannelMessage>>> + Send;
+
+    /// Posts a message to the channel.
+    fn post(
+        &self,
+        channel_id: ChannelId,
+        content: String,
+    ) -> impl Future<Output = Result<PostedMessage>> + Send;
+
+    /// Replaces the content of a message posted by the bot.
+    fn edit(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        content: String,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Returns the display name of a user.
+    fn user_name(&self, user_id: UserId) -> impl Future<Output = Result<String>> + Send;
+
+    /// Sends a direct message to a user.
+    fn dm(&self, user_id: UserId, content: String) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// A GuildApi for the guild a command was called from, which uses the discord API.
+pub struct SerenityGuild<'a> {
+    ctx: Context<'a>,
+    guild: Guild,
+}
+
+impl<'a> SerenityGuild<'a> {
+    pub fn new(ctx: Context<'a>) -> Result<Self> {
+        let guild = ctx
+            .guild()
+            .context("This command must be called from a guild (server).")?
+            .clone();
+        Ok(SerenityGuild { ctx, guild })
+    }
+}
+
+impl GuildApi for SerenityGuild<'_> {
+    fn guild_id(&self) -> GuildId {
+        self.guild.id
+    }
+
+    fn bot_id(&self) -> UserId {
+        self.ctx.framework().bot_id
+    }
+
+    fn role_by_name(&self, name: &str) -> Option<RoleId> {
+        self.guild.role_by_name(name).map(|r| r.id)
+    }
+
+    async fn members_with_role(&self, role_id: RoleId) -> Result<Vec<GuildMember>> {
+        if let Some(members) = self
+            .ctx
+            .data()
+            .member_index
+            .as_ref()
+            .and_then(|index| index.members_with_role(self.guild.id, role_id))
+        {
+            return Ok(members);
+        }
+
+        // max number of pages to try to fetch (to avoid infinite loops in the event of the server
+        // response format changing in a way that breaks the end-of-page detection)
+        const MAX_PAGES: u64 = 20;
+
+        // maximum number of members to request per page
+        const PAGE_LIMIT: u64 = 1000;
+
+        let mut last_member = None;
+        let mut members_with_role = Vec::new();
+
+        for _ in 0..MAX_PAGES {
+            let page = self
+                .guild
+                .members(self.ctx, Some(PAGE_LIMIT), last_member)
+                .await?;
+
+            members_with_role.extend(
+                page.iter()
+                    .filter(move |u| u.roles.iter().contains(&role_id))
+                    .map(GuildMember::from),
+            );
+
+            if page.len() < PAGE_LIMIT as usize {
+                break;
+            }
+            last_member = Some(
+                page.last()
+                    .expect("page is never empty here if PAGE_LIMIT > 0")
+                    .user
+                    .id,
+            );
+        }
+
+        Ok(members_with_role)
+    }
+
+    async fn find_channel(&self, name: &str) -> Result<Option<ChannelId>> {
+        Ok(self
+            .guild
+            .id
+            .channels(self.ctx)
+            .await?
+            .into_iter()
+            .find(|(_, c)| c.name == name)
+            .map(|(id, _)| id))
+    }
+
+    async fn messages_before(
+        &self,
+        channel_id: ChannelId,
+        before: Option<MessageId>,
+        limit: u8,
+    ) -> Result<Vec<ChannelMessage>> {
+        let mut request = GetMessages::new().limit(limit);
+        if let Some(before) = before {
+            request = request.before(before);
+        }
+        Ok(channel_id
+            .messages(self.ctx, request)
+            .await
+            .context("Error fetching message history")?
+            .into_iter()
+            .map(|m| ChannelMessage {
+                id: m.id,
+                author: m.author.id,
+                content: m.content,
+                timestamp: m.timestamp,
+                edited_timestamp: m.edited_timestamp,
+            })
+            .collect())
+    }
+
+    async fn post(&self, channel_id: ChannelId, content: String) -> Result<PostedMessage> {
+        let message = channel_id.say(self.ctx, content).await?;
+        Ok(PostedMessage {
+            id: message.id,
+            link: message.link(),
+        })
+    }
+
+    async fn edit(
+        &self,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        content: String,
+    ) -> Result<()> {
+        channel_id
+            .edit_message(self.ctx, message_id, EditMessage::new().content(content))
+            .await?;
+        Ok(())
+    }
+
+    async fn user_name(&self, user_id: UserId) -> Result<String> {
+        let user = user_id.to_user(self.ctx).await?;
+        Ok(user.global_name.unwrap_or(user.name))
+    }
+
+    async fn dm(&self, user_id: UserId, content: String) -> Result<()> {
+        user_id
+            .create_dm_channel(self.ctx)
+            .await?
+            .say(self.ctx, content)
+            .await?;
+        Ok(())
+    }
+}
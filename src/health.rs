@@ -0,0 +1,52 @@
+use serenity::all::{ConnectionStage, FullEvent};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// What the `/readyz` endpoint reports on.
+#[derive(Debug, Default)]
+pub struct Health {
+    gateway_connected: AtomicBool,
+    commands_registered: AtomicBool,
+    /// The result of the last periodic check of the REST API
+    rest_reachable: AtomicBool,
+}
+
+impl Health {
+    /// Records that the bot has connected to the gateway and registered its commands.
+    pub fn set_started(&self) {
+        self.gateway_connected.store(true, Ordering::Relaxed);
+        self.commands_registered.store(true, Ordering::Relaxed);
+    }
+
+    /// Records the result of a check of the REST API.
+    pub fn set_rest_reachable(&self, reachable: bool) {
+        self.rest_reachable.store(reachable, Ordering::Relaxed);
+    }
+
+    /// Tracks the gateway connection from gateway events.
+    pub fn handle_event(&self, event: &FullEvent) {
+        match event {
+            FullEvent::ShardStageUpdate { event } => self
+                .gateway_connected
+                .store(event.new == ConnectionStage::Connected, Ordering::Relaxed),
+            FullEvent::Ready { .. } | FullEvent::Resume { .. } => {
+                self.gateway_connected.store(true, Ordering::Relaxed)
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the reasons the bot is not ready, if any.
+    pub fn problems(&self) -> Vec<&'static str> {
+        let mut problems = Vec::new();
+        if !self.gateway_connected.load(Ordering::Relaxed) {
+            problems.push("not connected to the discord gateway");
+        }
+        if !self.commands_registered.load(Ordering::Relaxed) {
+            problems.push("commands are not registered");
+        }
+        if !self.rest_reachable.load(Ordering::Relaxed) {
+            problems.push("the discord REST API is not reachable");
+        }
+        problems
+    }
+}
@@ -1,9 +1,12 @@
-use crate::types::Data;
-use anyhow::Error;
+use crate::audit::{record_denied, record_failed_command, set_audit_outcome};
+use crate::config::MAX_SEED_LENGTH;
+use crate::types::{Context, Data};
+use anyhow::{ensure, Error};
 use itertools::Itertools;
 use poise::FrameworkError;
-use serenity::all::UserId;
+use serenity::all::{MessageId, Timestamp, UserId};
 use std::hash::{DefaultHasher, Hash, Hasher};
+use tracing::{error, field, info_span, Instrument, Span};
 
 /// A Match represents a single set of elements matched together. In the context of matchy meetups
 /// most Matches are pairs, but if there are an odd number there will be one 3-matching.
@@ -11,32 +14,138 @@ pub type Match<T> = Vec<T>;
 
 /// A pairing contains the matchings for some group of elements.
 /// The first element contains the matchings (each element will appear in exactly one Match)
-/// The second element contains the set of duplicated matchings, if any. These are the elements
-/// that were unable to be matched with unique elements. Each element in this second vector
-/// also appears somewhere in the first set of matchings.
-pub struct Pairing<T>(pub Vec<Match<T>>, pub Vec<T>);
+/// The second element contains the imperfect matches, if any. These are the elements that have been
+/// matched before with someone else in their Match, including the remainder of a triple. Each
+/// element in this second vector also appears somewhere in the first set of matchings.
+/// The third element is the number of pairs of elements within the matchings that have been
+/// matched before.
+/// The fourth element contains details about how each Match was made, in the same order as the
+/// matchings (it may be empty if these are not known).
+pub struct Pairing<T>(
+    pub Vec<Match<T>>,
+    pub Vec<T>,
+    pub usize,
+    pub Vec<MatchDetails<T>>,
+);
 
-/// Logs an error to stdout.
+/// Information about how a single Match was made, which is used to explain a pairing.
+pub struct MatchDetails<T> {
+    /// Pairs of elements in the Match that have been matched before (the history constraint was
+    /// relaxed for these)
+    pub repeated: Vec<(T, T)>,
+    /// Whether the Match was made because of a prefer-match rule
+    pub preferred: bool,
+    /// The element added to this Match because there was an odd number of elements, if any
+    pub remainder: Option<T>,
+    /// The sum of the soft pair scores between the elements of the Match
+    pub score: i64,
+}
+
+/// A Match from a previous round, along with when it was sent.
+#[derive(Clone, Debug)]
+pub struct PastMatch {
+    pub members: Match<UserId>,
+    pub timestamp: Timestamp,
+    /// The history message the Match was read from
+    pub message_id: MessageId,
+}
+
+/// The maximum length of a discord message
+pub const MESSAGE_LIMIT: usize = 2000;
+
+/// Creates the span for a command invocation. Commands that take a program or a seed record them
+/// in the `program` and `seed` fields.
+pub fn command_span(ctx: Context<'_>) -> Span {
+    info_span!(
+        "command",
+        command = %ctx.command().qualified_name,
+        guild = ctx.guild_id().map(|g| g.get()),
+        user = ctx.author().id.get(),
+        program = field::Empty,
+        seed = field::Empty,
+    )
+}
+
+/// Logs an error and reports it to the user.
 pub async fn handle_error(error: poise::FrameworkError<'_, Data, Error>) {
-    println!("Error: {:?}", error);
-
-    let Some(ctx) = error.ctx() else { return };
-    let error_res = match error {
-        FrameworkError::Command {
-            error: wrapped_error,
-            ..
-        } => {
-            ctx.say(format!("An unexpected error occurred: {:?}", wrapped_error))
-                .await
-        }
-        _ => ctx.say("An unknown error occurred").await,
+    let Some(ctx) = error.ctx() else {
+        error!("Error: {:?}", error);
+        return;
     };
-    if let Err(e) = error_res {
-        println!(
-            "A further error occurred sending the error message to discord: {:?}",
-            e
-        )
+    async {
+        error!("Error: {:?}", error);
+
+        let error_res = match error {
+            FrameworkError::Command {
+                error: wrapped_error,
+                ..
+            } => {
+                record_failed_command(ctx, &wrapped_error).await;
+                ctx.say(format!("An unexpected error occurred: {:?}", wrapped_error))
+                    .await
+            }
+            FrameworkError::MissingUserPermissions {
+                missing_permissions,
+                ..
+            } => {
+                let reason = match missing_permissions {
+                    Some(permissions) => format!("missing permissions: {permissions}"),
+                    None => "unable to check permissions".to_owned(),
+                };
+                record_denied(ctx, reason).await;
+                ctx.say("You don't have permission to use this command.")
+                    .await
+            }
+            FrameworkError::CommandCheckFailed { error, .. } => {
+                let reason = match &error {
+                    Some(e) => e.to_string(),
+                    None => "the permission check failed".to_owned(),
+                };
+                record_denied(ctx, reason.clone()).await;
+                match error {
+                    Some(_) => ctx.say(reason).await,
+                    None => {
+                        ctx.say("You don't have permission to use this command.")
+                            .await
+                    }
+                }
+            }
+            _ => ctx.say("An unknown error occurred").await,
+        };
+        if let Err(e) = error_res {
+            error!(
+                "A further error occurred sending the error message to discord: {:?}",
+                e
+            )
+        }
     }
+    .instrument(command_span(ctx))
+    .await
+}
+
+/// Sends the response of a command, or the error if there was one.
+pub async fn respond(ctx: Context<'_>, resp: Result<String, Error>) -> Result<(), Error> {
+    let resp = resp.unwrap_or_else(|e| format!("Error: {}", e));
+    set_audit_outcome(ctx, resp.clone()).await;
+    ctx.say(resp).await?;
+    Ok(())
+}
+
+/// Splits text into chunks that each fit within a discord message, breaking between lines.
+pub fn split_message(text: &str) -> Vec<String> {
+    let mut chunks = vec![String::new()];
+    for line in text.lines() {
+        let current = chunks.last_mut().expect("chunks is never empty");
+        if !current.is_empty() && current.len() + line.len() + 1 > MESSAGE_LIMIT {
+            chunks.push(String::new());
+        }
+        let current = chunks.last_mut().expect("chunks is never empty");
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    chunks
 }
 
 /// Hashes a string into a u64 that can be used as a seed
@@ -48,7 +157,7 @@ pub fn hash_seed(seed: &str) -> u64 {
 
 /// Generates a short checksum for a given seed & pairing, which can be used to verify that nothing
 /// has changed between multiple uses.
-pub fn checksum_matching<T: Hash>(seed: u64, pairs: &Vec<Match<T>>) -> String {
+pub fn checksum_matching<T: Hash>(seed: u64, pairs: &[Match<T>]) -> String {
     let mut hasher = DefaultHasher::new();
     seed.hash(&mut hasher);
     pairs.hash(&mut hasher);
@@ -56,13 +165,48 @@ pub fn checksum_matching<T: Hash>(seed: u64, pairs: &Vec<Match<T>>) -> String {
     hex[..8].to_string()
 }
 
+/// Checks that a seed can be used in a key. Keys are recorded inside a code block in the history
+/// channel, so they can't contain backticks, and they must be short enough that a round's record
+/// still fits in a message.
+pub fn validate_seed(seed_str: &str) -> Result<(), Error> {
+    ensure!(!seed_str.is_empty(), "The seed can't be empty.");
+    ensure!(
+        seed_str.chars().count() <= MAX_SEED_LENGTH,
+        "The seed can be at most {MAX_SEED_LENGTH} characters long."
+    );
+    ensure!(
+        !seed_str.contains('`'),
+        "The seed can't contain backticks (`)."
+    );
+    Ok(())
+}
+
+/// Creates the key for a pairing, which is used to send it with /send_pairing. The candidate is
+/// omitted when it is 0, so keys from before candidates were introduced remain valid.
+pub fn format_key(seed_str: &str, candidate: usize, checksum: &str) -> String {
+    if candidate == 0 {
+        format!("{seed_str}_{checksum}")
+    } else {
+        format!("{seed_str}_{checksum}.{candidate}")
+    }
+}
+
+/// Parses a key created by format_key() into the seed string, candidate and checksum.
+pub fn parse_key(key: &str) -> Option<(&str, usize, &str)> {
+    let (seed_str, rest) = key.rsplit_once('_')?;
+    match rest.split_once('.') {
+        Some((checksum, candidate)) => Some((seed_str, candidate.parse().ok()?, checksum)),
+        None => Some((seed_str, 0, rest)),
+    }
+}
+
 /// Formats an ID for display as a ping in discord
 pub fn format_id(id: &UserId) -> String {
     format!("<@{id}>")
 }
 
 /// Formats a pairing into a string suitable for a discord message
-pub fn format_pairs(pairs: &Vec<Match<UserId>>) -> String {
+pub fn format_pairs(pairs: &[Match<UserId>]) -> String {
     pairs
         .iter()
         .map(|p| {
@@ -0,0 +1,491 @@
+use crate::guild_api::{ChannelMessage, GuildApi, MESSAGE_PAGE_LIMIT};
+use crate::helpers::{Match, PastMatch, MESSAGE_LIMIT};
+use crate::storage::Lookback;
+use anyhow::{bail, ensure, Result};
+use chrono::{DateTime, Duration, Utc};
+use itertools::Itertools;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, FullEvent, MessageId, Timestamp, UserId};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::LazyLock;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+/// Edits made this soon after a message was posted are part of posting it (rounds used to be
+/// posted as a placeholder that was then edited), so they are not flagged.
+const EDIT_GRACE_PERIOD_SECONDS: i64 = 60;
+
+/// Whose messages in a history channel are read as rounds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HistoryAuthors {
+    /// Only rounds the bot posted, as in a program's history channel
+    Bot,
+    /// The bot's rounds and groups posted by anyone else, as in a channel an admin added as a
+    /// history source for ad-hoc events
+    Anyone,
+}
+
+/// The Matches posted in one history message (or in all parts of a structured round).
+#[derive(Clone, Debug)]
+struct PastRound {
+    /// The round ID of a structured round, which is shared by all of its parts
+    id: Option<String>,
+    timestamp: Timestamp,
+    matches: Vec<PastMatch>,
+}
+
+/// The previous rounds read from a history channel.
+#[derive(Debug, Default)]
+pub struct PastHistory {
+    /// The Matches within the lookback, newest first
+    pub matches: Vec<PastMatch>,
+    /// Rounds posted by the bot within the lookback that were edited after they were posted. These
+    /// are not trusted, so their Matches are not included.
+    pub edited_messages: Vec<MessageId>,
+}
+
+/// The rounds parsed from a range of history messages, newest first.
+#[derive(Debug, Default)]
+struct ParsedMessages {
+    rounds: Vec<PastRound>,
+    /// Rounds that were edited after they were posted
+    edited: Vec<(MessageId, Timestamp)>,
+}
+
+impl ParsedMessages {
+    /// Adds a message that is older than all of the messages added so far.
+    fn add(&mut self, bot_id: UserId, authors: HistoryAuthors, message: &ChannelMessage) {
+        let Some(round) = parse_round(bot_id, authors, message) else {
+            return;
+        };
+        // members fix typos in the groups they post, so only the bot's rounds are flagged
+        if message.author == bot_id && was_edited(message) {
+            warn!(
+                message = %message.id,
+                "Not using history message because it was edited after it was posted"
+            );
+            self.edited.push((message.id, message.timestamp));
+        } else {
+            self.append_round(round);
+        }
+    }
+
+    /// Appends rounds that are older than all of the rounds so far, merging a round with the
+    /// previous one if they are parts of the same structured round.
+    fn append_round(&mut self, round: PastRound) {
+        match self.rounds.last_mut() {
+            Some(last) if last.id.is_some() && last.id == round.id => {
+                last.matches.extend(round.matches)
+            }
+            _ => self.rounds.push(round),
+        }
+    }
+
+    /// Appends messages that are older than all of the messages so far.
+    fn append(&mut self, older: ParsedMessages) {
+        for round in older.rounds {
+            self.append_round(round);
+        }
+        self.edited.extend(older.edited);
+    }
+
+    /// Forgets the Matches in deleted messages, and any rounds left without Matches.
+    fn remove(&mut self, deleted: &[MessageId]) {
+        for round in &mut self.rounds {
+            round.matches.retain(|m| !deleted.contains(&m.message_id));
+        }
+        self.rounds.retain(|r| !r.matches.is_empty());
+        self.edited.retain(|(id, _)| !deleted.contains(id));
+    }
+}
+
+/// Returns true if a message was edited after it was posted.
+fn was_edited(message: &ChannelMessage) -> bool {
+    message.edited_timestamp.is_some_and(|edited| {
+        edited.unix_timestamp() - message.timestamp.unix_timestamp() > EDIT_GRACE_PERIOD_SECONDS
+    })
+}
+
+/// The rounds parsed from a contiguous range of a history channel's messages.
+#[derive(Debug, Default)]
+struct CachedChannel {
+    parsed: ParsedMessages,
+    /// The newest message that has been fetched
+    newest: Option<MessageId>,
+    /// The oldest message that has been fetched, which older messages are paged from
+    oldest: Option<(MessageId, Timestamp)>,
+    /// Whether the start of the channel has been reached
+    complete: bool,
+}
+
+impl CachedChannel {
+    /// Returns true if the cached rounds cover `lookback`.
+    fn covers(&self, lookback: Lookback, cutoff: DateTime<Utc>) -> bool {
+        match lookback {
+            Lookback::Days(_) => self.oldest.is_some_and(|(_, t)| *t < cutoff),
+            Lookback::Rounds(rounds) => self.parsed.rounds.len() >= rounds as usize,
+        }
+    }
+}
+
+/// Caches the rounds parsed from history channels between commands, so only messages newer than
+/// the newest cached message (or older than the oldest, if the lookback grows) are fetched.
+/// Deleted messages are removed using gateway events, but edits to messages that have already
+/// been cached are not seen until the bot restarts.
+#[derive(Debug, Default)]
+pub struct HistoryCache {
+    channels: Mutex<HashMap<(ChannelId, HistoryAuthors), CachedChannel>>,
+}
+
+/// The language of the code block that holds a structured round.
+const ROUND_BLOCK_LANGUAGE: &str = "matchy-round";
+
+/// The version of RoundRecord written by format_round().
+const ROUND_RECORD_VERSION: u32 = 1;
+
+/// The machine-readable record of (part of) a round, which send_pairing writes to the history
+/// channel.
+#[derive(Clone, Serialize, Deserialize)]
+struct RoundRecord {
+    version: u32,
+    /// The key of the pairing
+    round: String,
+    /// Large rounds are split across several messages, numbered from 1
+    part: usize,
+    parts: usize,
+    timestamp: Timestamp,
+    groups: Vec<Match<UserId>>,
+    /// A checksum of the other fields, which is left empty while it is calculated
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    checksum: String,
+}
+
+impl RoundRecord {
+    fn calculate_checksum(&self) -> String {
+        let mut unsigned = self.clone();
+        unsigned.checksum.clear();
+        let json = serde_json::to_string(&unsigned).expect("a round record should serialize");
+        let hex = format!("{:x}", Sha256::digest(json));
+        hex[..16].to_owned()
+    }
+}
+
+/// Formats the history messages for a round: each has a link to the notification message and a
+/// code block with a RoundRecord. The groups are split over as many messages as needed to fit
+/// within discord's message length limit.
+pub fn format_round(
+    round_id: &str,
+    timestamp: Timestamp,
+    link: &str,
+    groups: &[Match<UserId>],
+) -> Vec<String> {
+    let format_part = |part: usize, parts: usize, groups: &[Match<UserId>]| {
+        let mut record = RoundRecord {
+            version: ROUND_RECORD_VERSION,
+            round: round_id.to_owned(),
+            part,
+            parts,
+            timestamp,
+            groups: groups.to_vec(),
+            checksum: String::new(),
+        };
+        record.checksum = record.calculate_checksum();
+        let json = serde_json::to_string(&record).expect("a round record should serialize");
+        format!("{link}\n```{ROUND_BLOCK_LANGUAGE}\n{json}\n```")
+    };
+
+    // leave room for the part numbers, which are not known until the groups are split
+    let limit = MESSAGE_LIMIT - 20;
+    let mut chunks: Vec<&[Match<UserId>]> = Vec::new();
+    let mut start = 0;
+    for end in 1..=groups.len() {
+        if end - start > 1 && format_part(0, 0, &groups[start..end]).len() > limit {
+            chunks.push(&groups[start..end - 1]);
+            start = end - 1;
+        }
+    }
+    chunks.push(&groups[start..]);
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| format_part(i + 1, chunks.len(), chunk))
+        .collect()
+}
+
+/// Parses the RoundRecord in a history message. Returns None if the message does not have one,
+/// and Some(None) if it has one that cannot be used (because it has an unknown version or its
+/// checksum does not match).
+fn parse_structured_round(message: &ChannelMessage) -> Option<Option<PastRound>> {
+    let (_, rest) = message
+        .content
+        .split_once(&format!("```{ROUND_BLOCK_LANGUAGE}\n"))?;
+    let Some((json, _)) = rest.split_once("```") else {
+        return Some(None);
+    };
+    let record = match serde_json::from_str::<RoundRecord>(json) {
+        Ok(record) if record.version == ROUND_RECORD_VERSION => record,
+        _ => {
+            warn!(message = %message.id, "Ignoring unreadable round in history message");
+            return Some(None);
+        }
+    };
+    if record.checksum != record.calculate_checksum() {
+        warn!(
+            message = %message.id,
+            "Ignoring round in history message because its checksum does not match"
+        );
+        return Some(None);
+    }
+    Some(Some(PastRound {
+        id: Some(record.round),
+        timestamp: record.timestamp,
+        matches: record
+            .groups
+            .into_iter()
+            .map(|members| PastMatch {
+                members,
+                timestamp: record.timestamp,
+                message_id: message.id,
+            })
+            .collect(),
+    }))
+}
+
+static LEGACY_LINE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^<@[0-9]+>(, <@[0-9]+>)*,? and <@[0-9]+>$").expect("regex creation should succeed")
+});
+static MENTION_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<@([0-9]+)>").expect("regex creation should succeed"));
+
+/// Parses a history message in the format used before structured rounds: a link to the
+/// notification message, followed by one line per Match such as "<@1>, <@2>, and <@3>".
+fn parse_legacy_round(message: &ChannelMessage) -> Option<PastRound> {
+    let mut lines = message.content.lines();
+    if !lines.next()?.starts_with("https://discord.com/channels/") {
+        return None;
+    }
+    let lines: Vec<&str> = lines.map(str::trim).filter(|l| !l.is_empty()).collect();
+    if lines.is_empty() || !lines.iter().all(|l| LEGACY_LINE_RE.is_match(l)) {
+        return None;
+    }
+    let matches = lines
+        .iter()
+        .map(|line| PastMatch {
+            members: MENTION_RE
+                .captures_iter(line)
+                .map(|c| c.extract())
+                .flat_map(|(_, [id])| id.parse().ok())
+                .collect(),
+            timestamp: message.timestamp,
+            message_id: message.id,
+        })
+        .collect();
+    Some(PastRound {
+        id: None,
+        timestamp: message.timestamp,
+        matches,
+    })
+}
+
+/// Parses a message posted by a member in a history source channel. Each line with at least two
+/// members is a group, as in a history file, and a message without any groups is skipped.
+fn parse_member_round(message: &ChannelMessage) -> Option<PastRound> {
+    let matches: Vec<PastMatch> = message
+        .content
+        .lines()
+        .map(line_members)
+        .filter(|members| members.len() > 1)
+        .map(|members| PastMatch {
+            members,
+            timestamp: message.timestamp,
+            message_id: message.id,
+        })
+        .collect();
+    (!matches.is_empty()).then_some(PastRound {
+        id: None,
+        timestamp: message.timestamp,
+        matches,
+    })
+}
+
+/// Parses the Matches in a history message. Messages posted by anyone else are only read when
+/// `authors` is Anyone, since anyone who can post in a program's history channel could otherwise
+/// add or hide constraints.
+fn parse_round(
+    bot_id: UserId,
+    authors: HistoryAuthors,
+    message: &ChannelMessage,
+) -> Option<PastRound> {
+    if message.author != bot_id {
+        return match authors {
+            HistoryAuthors::Bot => None,
+            HistoryAuthors::Anyone => parse_member_round(message),
+        };
+    }
+    match parse_structured_round(message) {
+        Some(round) => round,
+        None => parse_legacy_round(message),
+    }
+}
+
+impl HistoryCache {
+    /// Removes the rounds in messages that were deleted from a channel.
+    pub async fn messages_deleted(&self, channel_id: ChannelId, deleted: &[MessageId]) {
+        let mut channels = self.channels.lock().await;
+        for ((channel, _), cached) in channels.iter_mut() {
+            if *channel == channel_id {
+                cached.parsed.remove(deleted);
+            }
+        }
+    }
+
+    /// Updates the cache from gateway events about deleted messages.
+    pub async fn handle_event(&self, event: &FullEvent) {
+        match event {
+            FullEvent::MessageDelete {
+                channel_id,
+                deleted_message_id,
+                ..
+            } => {
+                self.messages_deleted(*channel_id, &[*deleted_message_id])
+                    .await
+            }
+            FullEvent::MessageDeleteBulk {
+                channel_id,
+                multiple_deleted_messages_ids,
+                ..
+            } => {
+                self.messages_deleted(*channel_id, multiple_deleted_messages_ids)
+                    .await
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the previous Matches posted by `authors` in the history channel within `lookback`.
+    /// Messages that are not rounds (such as chatter) are skipped.
+    pub async fn previous_matches(
+        &self,
+        api: &impl GuildApi,
+        channel_id: ChannelId,
+        authors: HistoryAuthors,
+        lookback: Lookback,
+    ) -> Result<PastHistory> {
+        let bot_id = api.bot_id();
+        let cutoff = match lookback {
+            Lookback::Days(days) => Utc::now() - Duration::days(days.into()),
+            Lookback::Rounds(_) => DateTime::<Utc>::MIN_UTC,
+        };
+
+        // holding the lock while fetching stops concurrent commands from fetching the same pages
+        let mut channels = self.channels.lock().await;
+        let cached = channels.entry((channel_id, authors)).or_default();
+
+        // fetch messages newer than the cached ones
+        if cached.newest.is_some() || cached.complete {
+            let mut new_messages = ParsedMessages::default();
+            let mut newest = None;
+            let had_oldest = cached.oldest.is_some();
+            let mut before = None;
+            'pages: loop {
+                let page = api
+                    .messages_before(channel_id, before, MESSAGE_PAGE_LIMIT)
+                    .await?;
+                for message in &page {
+                    if cached.newest.is_some_and(|n| message.id <= n) {
+                        break 'pages;
+                    }
+                    newest.get_or_insert(message.id);
+                    if !had_oldest {
+                        cached.oldest = Some((message.id, message.timestamp));
+                    }
+                    new_messages.add(bot_id, authors, message);
+                }
+                match page.last() {
+                    Some(last) if page.len() == MESSAGE_PAGE_LIMIT as usize => {
+                        before = Some(last.id)
+                    }
+                    _ => break,
+                }
+            }
+            cached.newest = newest.or(cached.newest);
+            new_messages.append(std::mem::take(&mut cached.parsed));
+            cached.parsed = new_messages;
+        }
+
+        // fetch older messages until the lookback is covered
+        while !cached.complete && !cached.covers(lookback, cutoff) {
+            let page = api
+                .messages_before(
+                    channel_id,
+                    cached.oldest.map(|(id, _)| id),
+                    MESSAGE_PAGE_LIMIT,
+                )
+                .await?;
+            for message in &page {
+                cached.newest.get_or_insert(message.id);
+                cached.oldest = Some((message.id, message.timestamp));
+                cached.parsed.add(bot_id, authors, message);
+            }
+            cached.complete = page.len() < MESSAGE_PAGE_LIMIT as usize;
+        }
+
+        let rounds = cached.parsed.rounds.iter();
+        let rounds: Vec<&PastRound> = match lookback {
+            Lookback::Days(_) => rounds.take_while(|r| *r.timestamp >= cutoff).collect(),
+            Lookback::Rounds(n) => rounds.take(n as usize).collect(),
+        };
+        // for a lookback in rounds, flag edited messages newer than the oldest round used
+        let edited_cutoff = match (lookback, rounds.last()) {
+            (Lookback::Rounds(n), Some(oldest)) if rounds.len() == n as usize => oldest.timestamp,
+            (Lookback::Rounds(_), _) => Timestamp::from(DateTime::<Utc>::MIN_UTC),
+            (Lookback::Days(_), _) => Timestamp::from(cutoff),
+        };
+        Ok(PastHistory {
+            matches: rounds
+                .into_iter()
+                .flat_map(|r| r.matches.iter().cloned())
+                .collect(),
+            edited_messages: cached
+                .parsed
+                .edited
+                .iter()
+                .filter(|(_, timestamp)| *timestamp >= edited_cutoff)
+                .map(|(id, _)| *id)
+                .collect(),
+        })
+    }
+}
+
+static HISTORY_FILE_MEMBER_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"<@!?([0-9]+)>|\b([0-9]{17,20})\b").expect("regex creation should succeed")
+});
+
+/// Returns the members on a line of groups, written as mentions or user IDs.
+fn line_members(line: &str) -> Match<UserId> {
+    HISTORY_FILE_MEMBER_RE
+        .captures_iter(line)
+        .flat_map(|c| c.get(1).or(c.get(2)))
+        .flat_map(|id| id.as_str().parse().ok())
+        .unique()
+        .collect()
+}
+
+/// Parses the groups in an uploaded history file. Each line with members is one group, and the
+/// members are mentions (`<@123>`) or user IDs. Anything else, such as team names or headers, is
+/// ignored.
+pub fn parse_history_file(contents: &str) -> Result<Vec<Match<UserId>>> {
+    let mut groups = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let members = line_members(line);
+        match members.len() {
+            0 => {}
+            1 => bail!("Line {} has only one member.", i + 1),
+            _ => groups.push(members),
+        }
+    }
+    ensure!(!groups.is_empty(), "The file has no groups of members.");
+    Ok(groups)
+}
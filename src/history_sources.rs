@@ -0,0 +1,257 @@
+use crate::config::{MAX_HISTORY_FILE_SIZE, MAX_HISTORY_WEIGHT};
+use crate::guild_api::SerenityGuild;
+use crate::helpers::{command_span, handle_error, respond};
+use crate::history::parse_history_file;
+use crate::permissions::{require_configure, require_view};
+use crate::program::{autocomplete_program, Program, DEFAULT_PROGRAM};
+use crate::settings::{format_lookback, LookbackUnit};
+use crate::storage::{HistorySource, HistorySourceKind};
+use crate::types::Context;
+use anyhow::{ensure, Context as _, Result};
+use itertools::Itertools;
+use serenity::all::{Attachment, GuildChannel, Timestamp};
+use tracing::Instrument;
+
+async fn add_source(ctx: Context<'_>, program: &str, source: HistorySource) -> Result<String> {
+    let guild_id = ctx
+        .guild_id()
+        .context("This command must be called from a guild (server).")?;
+    ensure!(
+        (1..=MAX_HISTORY_WEIGHT).contains(&source.weight),
+        "The weight must be between 1 and {MAX_HISTORY_WEIGHT}."
+    );
+    let description = format!(
+        "Matching will avoid repeating groups from `{}` in the last {}, which each count as \
+        meeting {} time{}.",
+        source.name,
+        format_lookback(source.lookback),
+        source.weight,
+        if source.weight == 1 { "" } else { "s" }
+    );
+    ctx.data()
+        .store
+        .update_program(guild_id, program, |data| {
+            ensure!(
+                data.history_sources.iter().all(|s| s.name != source.name),
+                "There is already a history source named `{}`.",
+                source.name
+            );
+            data.history_sources.push(source);
+            Ok(())
+        })??;
+    Ok(description)
+}
+
+async fn handle_add_channel(
+    ctx: Context<'_>,
+    program: &str,
+    channel: &GuildChannel,
+    unit: LookbackUnit,
+    amount: u32,
+    weight: u32,
+) -> Result<String> {
+    let api = SerenityGuild::new(ctx)?;
+    let history_channel = Program::load(&api, ctx.data(), program)?
+        .history_channel(&api)
+        .await
+        .ok();
+    ensure!(
+        history_channel != Some(channel.id),
+        "<#{}> is already the history channel of `{program}`.",
+        channel.id
+    );
+    let source = HistorySource {
+        name: channel.name.clone(),
+        kind: HistorySourceKind::Channel(channel.id),
+        lookback: unit.lookback(amount)?,
+        weight,
+    };
+    add_source(ctx, program, source).await
+}
+
+async fn handle_add_file(
+    ctx: Context<'_>,
+    program: &str,
+    file: &Attachment,
+    name: Option<String>,
+    unit: LookbackUnit,
+    amount: u32,
+    weight: u32,
+) -> Result<String> {
+    ensure!(
+        file.size <= MAX_HISTORY_FILE_SIZE,
+        "History files can be at most {} KiB.",
+        MAX_HISTORY_FILE_SIZE / 1024
+    );
+    let contents = file
+        .download()
+        .await
+        .with_context(|| format!("Unable to download `{}`", file.filename))?;
+    let contents = String::from_utf8(contents)
+        .with_context(|| format!("`{}` is not a text file", file.filename))?;
+    let groups = parse_history_file(&contents)
+        .with_context(|| format!("Unable to read `{}`", file.filename))?;
+    let source = HistorySource {
+        name: name.unwrap_or_else(|| file.filename.clone()),
+        kind: HistorySourceKind::File {
+            uploaded: Timestamp::now(),
+            groups,
+        },
+        lookback: unit.lookback(amount)?,
+        weight,
+    };
+    add_source(ctx, program, source).await
+}
+
+async fn handle_remove_source(ctx: Context<'_>, program: &str, name: &str) -> Result<String> {
+    let guild_id = ctx
+        .guild_id()
+        .context("This command must be called from a guild (server).")?;
+    let removed = ctx.data().store.update_program(guild_id, program, |data| {
+        let before = data.history_sources.len();
+        data.history_sources.retain(|s| s.name != name);
+        before - data.history_sources.len()
+    })?;
+    Ok(if removed == 0 {
+        format!("There was no history source named `{name}`.")
+    } else {
+        format!("Removed the history source `{name}`.")
+    })
+}
+
+async fn handle_list_sources(ctx: Context<'_>, program: &str) -> Result<String> {
+    let guild_id = ctx
+        .guild_id()
+        .context("This command must be called from a guild (server).")?;
+    ctx.data().store.read_program(guild_id, program, |data| {
+        if data.history_sources.is_empty() {
+            return format!("`{program}` only uses its history channel.");
+        }
+        data.history_sources
+            .iter()
+            .map(|s| {
+                let kind = match &s.kind {
+                    HistorySourceKind::Channel(channel) => format!("<#{channel}>"),
+                    HistorySourceKind::File { uploaded, groups } => format!(
+                        "file with {} groups uploaded <t:{}:R>",
+                        groups.len(),
+                        uploaded.unix_timestamp()
+                    ),
+                };
+                format!(
+                    "`{}` ({kind}): last {}, weight {}",
+                    s.name,
+                    format_lookback(s.lookback),
+                    s.weight
+                )
+            })
+            .join("\n")
+    })
+}
+
+/// Manage other channels and files of previous groups that matching avoids repeating
+#[poise::command(
+    slash_command,
+    hide_in_help,
+    ephemeral,
+    check = "require_view",
+    subcommands("add_channel", "add_file", "remove", "list"),
+    on_error = "handle_error"
+)]
+pub async fn matchy_history(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Avoid repeating groups recorded in another channel, by the bot or by members
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_configure",
+    on_error = "handle_error"
+)]
+async fn add_channel(
+    ctx: Context<'_>,
+    #[description = "A channel of rounds the bot recorded, or of groups posted one per line."]
+    #[channel_types("Text")]
+    channel: GuildChannel,
+    #[description = "How many days or rounds to look back."] amount: u32,
+    #[description = "Whether the amount is in days or rounds."] unit: LookbackUnit,
+    #[description = "How many times each group counts as meeting (default 1)."] weight: Option<u32>,
+    #[description = "The program (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
+) -> Result<()> {
+    let program = program.as_deref().unwrap_or(DEFAULT_PROGRAM);
+    let resp = handle_add_channel(ctx, program, &channel, unit, amount, weight.unwrap_or(1))
+        .instrument(command_span(ctx))
+        .await;
+    respond(ctx, resp).await
+}
+
+/// Avoid repeating groups from a file, such as hackathon teams
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_configure",
+    on_error = "handle_error"
+)]
+async fn add_file(
+    ctx: Context<'_>,
+    #[description = "A text file with one group per line, as mentions or user IDs."]
+    file: Attachment,
+    #[description = "How many days to use the groups for (or any number of rounds for always)."]
+    amount: u32,
+    #[description = "Whether the amount is in days or rounds."] unit: LookbackUnit,
+    #[description = "How many times each group counts as meeting (default 1)."] weight: Option<u32>,
+    #[description = "A name for the source (default the file name)."] name: Option<String>,
+    #[description = "The program (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
+) -> Result<()> {
+    let program = program.as_deref().unwrap_or(DEFAULT_PROGRAM);
+    let resp = handle_add_file(ctx, program, &file, name, unit, amount, weight.unwrap_or(1))
+        .instrument(command_span(ctx))
+        .await;
+    respond(ctx, resp).await
+}
+
+/// Stop using a history source
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_configure",
+    on_error = "handle_error"
+)]
+async fn remove(
+    ctx: Context<'_>,
+    #[description = "The name of the history source."] name: String,
+    #[description = "The program (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
+) -> Result<()> {
+    let program = program.as_deref().unwrap_or(DEFAULT_PROGRAM);
+    let resp = handle_remove_source(ctx, program, &name)
+        .instrument(command_span(ctx))
+        .await;
+    respond(ctx, resp).await
+}
+
+/// List the history sources of a program
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_view",
+    on_error = "handle_error"
+)]
+async fn list(
+    ctx: Context<'_>,
+    #[description = "The program (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
+) -> Result<()> {
+    let program = program.as_deref().unwrap_or(DEFAULT_PROGRAM);
+    let resp = handle_list_sources(ctx, program)
+        .instrument(command_span(ctx))
+        .await;
+    respond(ctx, resp).await
+}
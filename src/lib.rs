@@ -0,0 +1,28 @@
+pub mod attributes;
+pub mod audit;
+pub mod availability;
+pub mod config;
+pub mod create_pairing;
+pub mod discord_helpers;
+#[cfg(feature = "test-support")]
+pub mod fake_guild;
+pub mod guild_api;
+pub mod health;
+pub mod helpers;
+pub mod history;
+pub mod history_sources;
+pub mod logging;
+pub mod match_rules;
+pub mod matching;
+pub mod member_index;
+pub mod metrics;
+pub mod permissions;
+pub mod profile;
+pub mod program;
+pub mod send_pairing;
+pub mod server;
+pub mod settings;
+pub mod storage;
+pub mod types;
+
+pub const ROLE_NAME: &str = "matchy-meetups";
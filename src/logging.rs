@@ -0,0 +1,19 @@
+use crate::config::{LOG_ENV_VAR, LOG_FORMAT_ENV_VAR};
+use tracing_subscriber::EnvFilter;
+
+/// The log filter used when LOG_ENV_VAR is not set
+const DEFAULT_LOG_FILTER: &str = "warn,matchy_meetups_bot=info";
+
+/// Sets up logging to stdout. The verbosity is read from LOG_ENV_VAR (using the `RUST_LOG`
+/// syntax, such as `debug` or `warn,matchy_meetups_bot=trace`), and logs are written as JSON
+/// lines if LOG_FORMAT_ENV_VAR is `json`.
+pub fn init_logging() {
+    let filter =
+        EnvFilter::try_from_env(LOG_ENV_VAR).unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_FILTER));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    if std::env::var(LOG_FORMAT_ENV_VAR).is_ok_and(|f| f == "json") {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
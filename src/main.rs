@@ -1,26 +1,76 @@
-mod config;
-mod create_pairing;
-mod discord_helpers;
-mod helpers;
-mod matching;
-mod send_pairing;
-mod types;
-use crate::create_pairing::create_pairing;
-use crate::helpers::handle_error;
-use crate::send_pairing::send_pairing;
+use anyhow::{Context as _, Result};
+use matchy_meetups_bot::attributes::matching_attributes;
+use matchy_meetups_bot::audit::record_command;
+use matchy_meetups_bot::config::{
+    DATA_PATH_ENV_VAR, DEFAULT_DATA_PATH, DEFAULT_HTTP_PORT, HTTP_PORT_ENV_VAR,
+    MEMBER_INTENT_ENV_VAR,
+};
+use matchy_meetups_bot::create_pairing::create_pairing;
+use matchy_meetups_bot::health::Health;
+use matchy_meetups_bot::helpers::handle_error;
+use matchy_meetups_bot::history_sources::matchy_history;
+use matchy_meetups_bot::logging::init_logging;
+use matchy_meetups_bot::match_rules::match_rules;
+use matchy_meetups_bot::member_index::MemberIndex;
+use matchy_meetups_bot::metrics::Metrics;
+use matchy_meetups_bot::profile::matchy;
+use matchy_meetups_bot::program::matchy_programs;
+use matchy_meetups_bot::send_pairing::send_pairing;
+use matchy_meetups_bot::server::{self, ServerState};
+use matchy_meetups_bot::settings::matchy_settings;
+use matchy_meetups_bot::storage::Store;
+use matchy_meetups_bot::types::Data;
 use poise::serenity_prelude as serenity;
 use std::sync::Arc;
 
-pub const ROLE_NAME: &str = "matchy-meetups";
-
 #[tokio::main]
-async fn main() {
-    let token = std::env::var("DISCORD_TOKEN").expect("missing DISCORD_TOKEN");
-    let intents = serenity::GatewayIntents::non_privileged();
+async fn main() -> Result<()> {
+    init_logging();
+    let token = std::env::var("DISCORD_TOKEN").context("missing DISCORD_TOKEN")?;
+    let use_member_intent = std::env::var(MEMBER_INTENT_ENV_VAR).is_ok_and(|v| v == "true");
+    let mut intents = serenity::GatewayIntents::non_privileged();
+    if use_member_intent {
+        intents |= serenity::GatewayIntents::GUILD_MEMBERS;
+    }
+    let data_path =
+        std::env::var(DATA_PATH_ENV_VAR).unwrap_or_else(|_| DEFAULT_DATA_PATH.to_owned());
+    let store = Store::load(data_path).context("unable to load stored data")?;
+    let http_port = match std::env::var(HTTP_PORT_ENV_VAR) {
+        Ok(port) => port
+            .parse()
+            .with_context(|| format!("{HTTP_PORT_ENV_VAR} should be a port number"))?,
+        Err(_) => DEFAULT_HTTP_PORT,
+    };
+
+    let metrics = Arc::new(Metrics::default());
+    let health = Arc::new(Health::default());
+    let data_metrics = metrics.clone();
+    let data_health = health.clone();
 
     let framework = poise::Framework::builder()
         .options(poise::FrameworkOptions {
             on_error: |err| Box::pin(handle_error(err)),
+            pre_command: |ctx| {
+                Box::pin(async move {
+                    ctx.data()
+                        .metrics
+                        .commands
+                        .with_label_values(&[&ctx.command().qualified_name])
+                        .inc();
+                })
+            },
+            post_command: |ctx| Box::pin(record_command(ctx)),
+            event_handler: |ctx, event, _framework, data| {
+                Box::pin(async move {
+                    data.metrics.handle_event(event);
+                    data.health.handle_event(event);
+                    data.history_cache.handle_event(event).await;
+                    if let Some(index) = &data.member_index {
+                        index.handle_event(ctx, event);
+                    }
+                    Ok(())
+                })
+            },
             prefix_options: poise::PrefixFrameworkOptions {
                 prefix: Some("~".into()),
                 edit_tracker: Some(Arc::new(poise::EditTracker::for_timespan(
@@ -29,19 +79,40 @@ async fn main() {
                 case_insensitive_commands: true,
                 ..Default::default()
             },
-            commands: vec![create_pairing(), send_pairing()],
+            commands: vec![
+                create_pairing(),
+                send_pairing(),
+                match_rules(),
+                matching_attributes(),
+                matchy(),
+                matchy_settings(),
+                matchy_programs(),
+                matchy_history(),
+            ],
             ..Default::default()
         })
-        .setup(|ctx, _ready, framework| {
+        .setup(move |ctx, _ready, framework| {
             Box::pin(async move {
                 poise::builtins::register_globally(ctx, &framework.options().commands).await?;
-                Ok(())
+                data_health.set_started();
+                Ok(Data {
+                    member_index: use_member_intent.then(MemberIndex::default),
+                    metrics: data_metrics,
+                    health: data_health,
+                    ..Data::new(store)
+                })
             })
         })
         .build();
 
-    let client = serenity::ClientBuilder::new(token, intents)
+    let mut client = serenity::ClientBuilder::new(token, intents)
         .framework(framework)
-        .await;
-    client.unwrap().start().await.unwrap();
+        .await
+        .context("unable to create the discord client")?;
+    let rest_check = server::check_rest_api(client.http.clone(), health.clone());
+    let state = ServerState { metrics, health };
+    tokio::try_join!(server::serve(http_port, state), rest_check, async {
+        client.start().await.context("the discord client stopped")
+    })?;
+    Ok(())
 }
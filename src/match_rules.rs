@@ -0,0 +1,221 @@
+use crate::helpers::{command_span, format_id, handle_error, respond};
+use crate::permissions::{require_configure, require_view};
+use crate::program::{autocomplete_program, DEFAULT_PROGRAM};
+use crate::types::Context;
+use anyhow::{ensure, Context as _, Result};
+use itertools::Itertools;
+use serenity::all::{User, UserId};
+use tracing::Instrument;
+
+/// Which list of rules a command operates on.
+#[derive(Clone, Copy)]
+enum RuleKind {
+    Never,
+    Prefer,
+}
+
+/// Returns true if the unordered pair (a, b) matches `rule`.
+fn is_same_pair(rule: &(UserId, UserId), a: UserId, b: UserId) -> bool {
+    *rule == (a, b) || *rule == (b, a)
+}
+
+async fn handle_add_rule(
+    ctx: Context<'_>,
+    program: &str,
+    kind: RuleKind,
+    a: User,
+    b: User,
+) -> Result<String> {
+    let guild_id = ctx
+        .guild_id()
+        .context("This command must be called from a guild (server).")?;
+    ensure!(a.id != b.id, "A rule needs two different members.");
+    ctx.data().store.update_program(guild_id, program, |data| {
+        // a pair can only be in one of the lists at a time
+        data.never_match.retain(|r| !is_same_pair(r, a.id, b.id));
+        data.prefer_match.retain(|r| !is_same_pair(r, a.id, b.id));
+        match kind {
+            RuleKind::Never => data.never_match.push((a.id, b.id)),
+            RuleKind::Prefer => data.prefer_match.push((a.id, b.id)),
+        }
+    })?;
+    Ok(match kind {
+        RuleKind::Never => format!(
+            "{} and {} will never be matched together.",
+            format_id(&a.id),
+            format_id(&b.id)
+        ),
+        RuleKind::Prefer => format!(
+            "{} and {} will be matched together whenever possible.",
+            format_id(&a.id),
+            format_id(&b.id)
+        ),
+    })
+}
+
+async fn handle_remove_rule(ctx: Context<'_>, program: &str, a: User, b: User) -> Result<String> {
+    let guild_id = ctx
+        .guild_id()
+        .context("This command must be called from a guild (server).")?;
+    let removed = ctx.data().store.update_program(guild_id, program, |data| {
+        let before = data.never_match.len() + data.prefer_match.len();
+        data.never_match.retain(|r| !is_same_pair(r, a.id, b.id));
+        data.prefer_match.retain(|r| !is_same_pair(r, a.id, b.id));
+        before - data.never_match.len() - data.prefer_match.len()
+    })?;
+    Ok(if removed == 0 {
+        format!(
+            "There was no rule for {} and {}.",
+            format_id(&a.id),
+            format_id(&b.id)
+        )
+    } else {
+        format!(
+            "Removed the rule for {} and {}.",
+            format_id(&a.id),
+            format_id(&b.id)
+        )
+    })
+}
+
+async fn handle_list_rules(ctx: Context<'_>, program: &str) -> Result<String> {
+    let guild_id = ctx
+        .guild_id()
+        .context("This command must be called from a guild (server).")?;
+    let format_rules = |rules: &Vec<(UserId, UserId)>| {
+        if rules.is_empty() {
+            "(none)".to_owned()
+        } else {
+            rules
+                .iter()
+                .map(|(a, b)| format!("{} and {}", format_id(a), format_id(b)))
+                .join("\n")
+        }
+    };
+    ctx.data().store.read_program(guild_id, program, |data| {
+        format!(
+            "**Never match:**\n{}\n**Prefer to match:**\n{}",
+            format_rules(&data.never_match),
+            format_rules(&data.prefer_match)
+        )
+    })
+}
+
+/// Manage rules about which members should or should not be matched together
+#[poise::command(
+    slash_command,
+    hide_in_help,
+    ephemeral,
+    check = "require_view",
+    subcommands("never", "prefer", "remove", "list"),
+    on_error = "handle_error"
+)]
+pub async fn match_rules(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Never match two members together (for example, siblings)
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_configure",
+    on_error = "handle_error"
+)]
+async fn never(
+    ctx: Context<'_>,
+    #[description = "The first member."] a: User,
+    #[description = "The second member."] b: User,
+    #[description = "The program (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
+) -> Result<()> {
+    respond(
+        ctx,
+        handle_add_rule(
+            ctx,
+            program.as_deref().unwrap_or(DEFAULT_PROGRAM),
+            RuleKind::Never,
+            a,
+            b,
+        )
+        .instrument(command_span(ctx))
+        .await,
+    )
+    .await
+}
+
+/// Match two members together whenever possible (for example, a mentor and a mentee)
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_configure",
+    on_error = "handle_error"
+)]
+async fn prefer(
+    ctx: Context<'_>,
+    #[description = "The first member."] a: User,
+    #[description = "The second member."] b: User,
+    #[description = "The program (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
+) -> Result<()> {
+    respond(
+        ctx,
+        handle_add_rule(
+            ctx,
+            program.as_deref().unwrap_or(DEFAULT_PROGRAM),
+            RuleKind::Prefer,
+            a,
+            b,
+        )
+        .instrument(command_span(ctx))
+        .await,
+    )
+    .await
+}
+
+/// Remove any rule between two members
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_configure",
+    on_error = "handle_error"
+)]
+async fn remove(
+    ctx: Context<'_>,
+    #[description = "The first member."] a: User,
+    #[description = "The second member."] b: User,
+    #[description = "The program (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
+) -> Result<()> {
+    respond(
+        ctx,
+        handle_remove_rule(ctx, program.as_deref().unwrap_or(DEFAULT_PROGRAM), a, b)
+            .instrument(command_span(ctx))
+            .await,
+    )
+    .await
+}
+
+/// List all match rules for this server
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_view",
+    on_error = "handle_error"
+)]
+async fn list(
+    ctx: Context<'_>,
+    #[description = "The program (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
+) -> Result<()> {
+    respond(
+        ctx,
+        handle_list_rules(ctx, program.as_deref().unwrap_or(DEFAULT_PROGRAM))
+            .instrument(command_span(ctx))
+            .await,
+    )
+    .await
+}
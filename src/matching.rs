@@ -1,14 +1,15 @@
-use crate::helpers::{Match, Pairing};
+use crate::helpers::{Match, MatchDetails, Pairing};
 use anyhow::{bail, ensure, Context, Result};
 use itertools::Itertools;
-use petgraph::algo::{maximum_matching, Matching};
+use petgraph::algo::maximum_matching;
+use petgraph::graph::NodeIndex;
 use petgraph::matrix_graph::MatrixGraph;
 use petgraph::Undirected;
 use rand::prelude::SliceRandom;
 use rand::SeedableRng;
 use std::cmp::{max, min};
 use std::collections::{HashMap, HashSet};
-use std::hash::Hash;
+use std::hash::{DefaultHasher, Hash, Hasher};
 
 type NodeId = u16; // for adjacency matrix
 
@@ -32,7 +33,7 @@ pub fn random_pair<T: Clone>(vec: Vec<T>, seed: u64) -> Pairing<T> {
     let remainder = chunks.remainder();
     let mut x: Vec<Vec<T>> = chunks.map(|chunk| chunk.to_vec()).collect();
     x.last_mut().unwrap().extend_from_slice(remainder);
-    Pairing(x, Vec::new())
+    Pairing(x, Vec::new(), 0, Vec::new())
 }
 
 type UnMatrix = MatrixGraph<(), (), Undirected, Option<()>, NodeId>;
@@ -53,11 +54,68 @@ impl ConstraintEdge {
     }
 }
 
+/// A soft score for placing two elements in the same Match. Higher is better.
+pub type PairScore<T> = Box<dyn Fn(&T, &T) -> i64 + Send + Sync>;
+
+/// Rules set by admins about specific elements, applied on top of the history constraints.
+pub struct MatchingOptions<T> {
+    /// Pairs of elements that must never be placed in the same Match. These are strictly enforced;
+    /// if they cannot be satisfied, graph_pair() returns an error.
+    pub never_match: Vec<(T, T)>,
+    /// Pairs of elements that should be matched together whenever both are present. These take
+    /// precedence over the history constraints.
+    pub prefer_match: Vec<(T, T)>,
+    /// Soft scores which are summed to rate a potential Match. After the constraints are
+    /// satisfied, pairs are swapped around to increase the total score.
+    pub pair_scores: Vec<PairScore<T>>,
+    /// If set, elements are matched as mentors and mentees instead of with each other.
+    pub mentorship: Option<Mentorship<T>>,
+    /// Groups from outside the previous pairings, such as teams or another program's rounds.
+    /// These count as meetings when avoiding repeats (a group listed twice counts twice), but not
+    /// as previous triples, since they can be any size.
+    pub other_history: Vec<Match<T>>,
+}
+
+impl<T> Default for MatchingOptions<T> {
+    fn default() -> Self {
+        MatchingOptions {
+            never_match: Vec::new(),
+            prefer_match: Vec::new(),
+            pair_scores: Vec::new(),
+            mentorship: None,
+            other_history: Vec::new(),
+        }
+    }
+}
+
+/// Which elements are mentors when matching mentors with mentees. Every Match has exactly one
+/// mentor, followed by between one and `capacity` mentees.
+pub struct Mentorship<T> {
+    /// The elements who are mentors; every other element is a mentee
+    pub mentors: HashSet<T>,
+    /// The most mentees a mentor can be matched with
+    pub capacity: usize,
+}
+
+/// The constraint sets used while matching, in terms of node indices.
+struct Constraints {
+    /// Edges between elements that have been matched before, with the number of times they have
+    /// been matched
+    history: HashMap<ConstraintEdge, usize>,
+    /// Edges between elements that must never be matched
+    forbidden: HashSet<ConstraintEdge>,
+    /// Edges between elements that should be matched if possible
+    preferred: HashSet<ConstraintEdge>,
+    /// The number of previous triples (or larger Matches) each node has been in
+    previous_triples: Vec<usize>,
+}
+
 /// Creates "pairs" from the vector (Some triples may be created if necessary).
 /// Uses a graph matching algorithm.
 pub fn graph_pair<T: Hash + Eq + Copy>(
     vec: Vec<T>,
-    previous_pairings: &Vec<Match<T>>,
+    previous_pairings: &[Match<T>],
+    options: &MatchingOptions<T>,
     seed: u64,
 ) -> Result<Pairing<T>> {
     if vec.len() < 2 {
@@ -66,55 +124,458 @@ pub fn graph_pair<T: Hash + Eq + Copy>(
     if vec.len() > 200 {
         bail!("Exceeded the 200-element limit of graph_pair() (this can be increased if we verify performance)");
     }
+    ensure!(
+        vec.iter().all_unique(),
+        "Cannot pair elements that appear more than once."
+    );
     let vec = shuffled(vec, seed);
+    if let Some(mentorship) = &options.mentorship {
+        return mentorship_pair(vec, previous_pairings, options, mentorship);
+    }
+    let node_count = vec.len();
 
-    let (graph, constraints) = build_matching_graph(&vec, previous_pairings);
-    let matching = maximum_matching(&graph);
+    let constraints = build_constraints(&vec, previous_pairings, options);
 
-    let matched: Vec<Match<NodeId>> = matching
-        .edges()
-        .map(|(a, b)| vec![a.index() as NodeId, b.index() as NodeId])
-        .collect();
+    // preferred pairs are matched first, and are then excluded from the main matching
+    let preferred = match_edges(
+        node_count,
+        &HashSet::new(),
+        constraints
+            .preferred
+            .iter()
+            .filter(|e| !constraints.forbidden.contains(e))
+            .map(|e| (e.lower, e.upper)),
+    );
+    let locked: HashSet<NodeId> = preferred.iter().flatten().cloned().collect();
 
-    // this assumption is used when iterating over matchings in add_remainder
-    ensure!(!matched.is_empty(), "Matching was unexpectedly empty");
+    let score = |a: NodeId, b: NodeId| {
+        options
+            .pair_scores
+            .iter()
+            .map(|f| f(&vec[a as usize], &vec[b as usize]))
+            .sum::<i64>()
+    };
 
-    let (imperfect_match_pairs, remainder) = pair_unmatched(&graph, &matching);
+    // with an odd number of members, pick who will join a triple before matching everyone else
+    let chosen_remainder = choose_remainder(node_count, &locked, &constraints);
+    let excluded: HashSet<NodeId> = locked.iter().cloned().chain(chosen_remainder).collect();
 
-    // add remainder to matched
-    let (matched_with_remainder, remainder_match_score) =
-        add_remainder_to_pairing(matched, remainder, &constraints)?;
+    let graph = build_matching_graph(node_count, &excluded, &constraints);
+    let mut free_matched = max_matching_pairs(&graph);
+    if !options.pair_scores.is_empty() {
+        improve_matching(&mut free_matched, &constraints, &score);
+    }
+    let matched: Vec<Match<NodeId>> = preferred.into_iter().chain(free_matched).collect();
+
+    let (unmatched_pairs, leftover) =
+        pair_unmatched(node_count, &matched, chosen_remainder, &constraints)?;
+    let remainder = chosen_remainder.or(leftover);
+
+    let mut all_pairs: Vec<Match<NodeId>> = matched.into_iter().chain(unmatched_pairs).collect();
+    reduce_repeats(&mut all_pairs, &locked, &constraints);
 
     let index_to_element = |i: NodeId| vec[i as usize];
 
-    let imperfect_matches = {
-        let mut v: Vec<_> = imperfect_match_pairs
+    // add remainder to matched
+    let matched_with_remainder =
+        add_remainder_to_pairing(all_pairs, remainder, &constraints, &score)?;
+
+    let imperfect_matches: Vec<_> = repeated_members(&matched_with_remainder, &constraints)
+        .map(index_to_element)
+        .collect();
+
+    let details: Vec<MatchDetails<T>> = matched_with_remainder
+        .iter()
+        .map(|m| MatchDetails {
+            repeated: m
+                .iter()
+                .cloned()
+                .tuple_combinations()
+                .filter(|e| constraints.history.contains_key(&ConstraintEdge::new(*e)))
+                .map(|(a, b)| (index_to_element(a), index_to_element(b)))
+                .collect(),
+            preferred: m.iter().any(|x| locked.contains(x)),
+            remainder: remainder.filter(|r| m.contains(r)).map(index_to_element),
+            score: m
+                .iter()
+                .cloned()
+                .tuple_combinations()
+                .map(|(a, b)| score(a, b))
+                .sum(),
+        })
+        .collect();
+    let repeated_pairs = details.iter().map(|d| d.repeated.len()).sum();
+
+    let matched_with_remainder = matched_with_remainder
+        .into_iter()
+        .map(|m| m.into_iter().map(index_to_element).collect())
+        .collect();
+
+    Ok(Pairing(
+        matched_with_remainder,
+        imperfect_matches,
+        repeated_pairs,
+        details,
+    ))
+}
+
+/// Creates groups of one mentor and between one and `mentorship.capacity` mentees from the
+/// (already shuffled) vector. Mentors are never matched with each other, and mentees are only
+/// matched with each other through a shared mentor. The history constraints apply to every pair
+/// in a group, including mentees who share a mentor.
+fn mentorship_pair<T: Hash + Eq + Copy>(
+    vec: Vec<T>,
+    previous_pairings: &[Match<T>],
+    options: &MatchingOptions<T>,
+    mentorship: &Mentorship<T>,
+) -> Result<Pairing<T>> {
+    // bounds the running time; in practice this converges after a few passes
+    const MAX_PASSES: usize = 20;
+
+    let (mentors, mentees): (Vec<T>, Vec<T>) = vec
+        .into_iter()
+        .partition(|x| mentorship.mentors.contains(x));
+    let capacity = mentorship.capacity.max(1);
+    ensure!(
+        !mentors.is_empty(),
+        "There are no mentors to match the mentees with."
+    );
+    ensure!(
+        mentees.len() >= mentors.len(),
+        "There are more mentors ({}) than mentees ({}), so some mentors would have no mentee.",
+        mentors.len(),
+        mentees.len()
+    );
+    ensure!(
+        mentees.len() <= mentors.len() * capacity,
+        "{} mentors can take at most {} mentees, but there are {}.",
+        mentors.len(),
+        mentors.len() * capacity,
+        mentees.len()
+    );
+    // mentors are the nodes before `mentor_count`, and mentees are the rest
+    let mentor_count = mentors.len();
+    let vec: Vec<T> = mentors.into_iter().chain(mentees).collect();
+    let node_count = vec.len();
+    let constraints = build_constraints(&vec, previous_pairings, options);
+    let score = |a: NodeId, b: NodeId| {
+        options
+            .pair_scores
+            .iter()
+            .map(|f| f(&vec[a as usize], &vec[b as usize]))
+            .sum::<i64>()
+    };
+    let forbidden =
+        |a: NodeId, b: NodeId| constraints.forbidden.contains(&ConstraintEdge::new((a, b)));
+    let met = |a: NodeId, b: NodeId| {
+        constraints
+            .history
+            .contains_key(&ConstraintEdge::new((a, b)))
+    };
+    let members = |mentor: usize, group: &[NodeId]| -> Vec<NodeId> {
+        std::iter::once(mentor as NodeId)
+            .chain(group.iter().cloned())
+            .collect()
+    };
+    // whether a mentee can join a group without breaking a never-match rule
+    let fits = |mentor: usize, group: &[NodeId], mentee: NodeId| {
+        !members(mentor, group).iter().any(|x| forbidden(*x, mentee))
+    };
+    // whether a mentee can join a group without repeating a previous match
+    let is_new = |mentor: usize, group: &[NodeId], mentee: NodeId| {
+        fits(mentor, group, mentee) && !members(mentor, group).iter().any(|x| met(*x, mentee))
+    };
+    // the number of repeats, then the negated score, of adding a mentee to a group
+    let cost = |mentor: usize, group: &[NodeId], mentee: NodeId| -> (i64, i64) {
+        members(mentor, group)
+            .iter()
+            .fold((0, 0), |(repeats, neg_score), x| {
+                let edge = ConstraintEdge::new((*x, mentee));
+                let count = constraints.history.get(&edge).copied().unwrap_or(0);
+                (repeats + count as i64, neg_score - score(*x, mentee))
+            })
+    };
+
+    let mut groups: Vec<Vec<NodeId>> = vec![Vec::new(); mentor_count];
+    let mut unassigned: Vec<NodeId> = (mentor_count as NodeId..node_count as NodeId).collect();
+
+    // preferred mentees are placed first, and are then not moved between groups
+    let mut locked: HashSet<NodeId> = HashSet::new();
+    for (mentor, mentee) in constraints
+        .preferred
+        .iter()
+        .map(|e| (e.lower, e.upper))
+        .filter(|(a, b)| (*a as usize) < mentor_count && (*b as usize) >= mentor_count)
+        .sorted()
+    {
+        let group = &groups[mentor as usize];
+        if unassigned.contains(&mentee)
+            && group.len() < capacity
+            && fits(mentor as usize, group, mentee)
+        {
+            groups[mentor as usize].push(mentee);
+            unassigned.retain(|x| *x != mentee);
+            locked.insert(mentee);
+        }
+    }
+
+    // give every mentor a mentee they haven't met, as far as possible
+    let without_mentee: Vec<usize> = (0..mentor_count)
+        .filter(|m| groups[*m].is_empty())
+        .collect();
+    let first_mentees = match_edges(
+        node_count,
+        &HashSet::new(),
+        without_mentee
+            .iter()
+            .cartesian_product(&unassigned)
+            .filter(|(mentor, mentee)| is_new(**mentor, &[], **mentee))
+            .map(|(mentor, mentee)| (*mentor as NodeId, *mentee)),
+    );
+    for pair in first_mentees {
+        let (mentor, mentee) = (pair[0].min(pair[1]), pair[0].max(pair[1]));
+        groups[mentor as usize].push(mentee);
+        unassigned.retain(|x| *x != mentee);
+    }
+    // then any mentee, taking one from a larger group if there are none left
+    for mentor in 0..mentor_count {
+        if !groups[mentor].is_empty() {
+            continue;
+        }
+        let from_unassigned = unassigned
             .iter()
-            .flatten()
             .cloned()
-            .map(index_to_element)
+            .filter(|e| fits(mentor, &[], *e))
+            .min_by_key(|e| (cost(mentor, &[], *e), *e));
+        if let Some(mentee) = from_unassigned {
+            groups[mentor].push(mentee);
+            unassigned.retain(|x| *x != mentee);
+            continue;
+        }
+        let (other, mentee) = (0..mentor_count)
+            .filter(|other| groups[*other].len() > 1)
+            .flat_map(|other| groups[other].iter().map(move |e| (other, *e)))
+            .filter(|(_, e)| fits(mentor, &[], *e))
+            .min_by_key(|(_, e)| (locked.contains(e), cost(mentor, &[], *e), *e))
+            .context("Unable to give every mentor a mentee without breaking a never-match rule")?;
+        groups[other].retain(|x| *x != mentee);
+        groups[mentor].push(mentee);
+        locked.remove(&mentee);
+    }
+
+    // fill the groups one mentee at a time, which keeps them about the same size
+    loop {
+        let open: Vec<usize> = (0..mentor_count)
+            .filter(|m| groups[*m].len() < capacity)
             .collect();
-        if let Some(remainder) = remainder {
-            if remainder_match_score > 0 {
-                v.push(index_to_element(remainder));
+        let next_mentees = match_edges(
+            node_count,
+            &HashSet::new(),
+            open.iter()
+                .cartesian_product(&unassigned)
+                .filter(|(mentor, mentee)| is_new(**mentor, &groups[**mentor], **mentee))
+                .map(|(mentor, mentee)| (*mentor as NodeId, *mentee)),
+        );
+        if next_mentees.is_empty() {
+            break;
+        }
+        for pair in next_mentees {
+            let (mentor, mentee) = (pair[0].min(pair[1]), pair[0].max(pair[1]));
+            groups[mentor as usize].push(mentee);
+            unassigned.retain(|x| *x != mentee);
+        }
+    }
+    // the rest repeat previous matches, with whoever they have met the fewest times
+    for mentee in std::mem::take(&mut unassigned) {
+        let mentor = (0..mentor_count)
+            .filter(|m| groups[*m].len() < capacity && fits(*m, &groups[*m], mentee))
+            .min_by_key(|m| (cost(*m, &groups[*m], mentee), groups[*m].len(), *m))
+            .context("Unable to place every mentee without breaking a never-match rule")?;
+        groups[mentor].push(mentee);
+    }
+
+    // move and swap mentees between groups while that reduces repeats or increases the score
+    for _ in 0..MAX_PASSES {
+        let mut improved = false;
+        for (i, j) in (0..mentor_count).tuple_combinations() {
+            for (from, to) in [(i, j), (j, i)] {
+                for mentee in groups[from].clone() {
+                    let without: Vec<NodeId> = groups[from]
+                        .iter()
+                        .cloned()
+                        .filter(|x| *x != mentee)
+                        .collect();
+                    let current = cost(from, &without, mentee);
+                    // swaps that help the other mentee are found from the other group
+                    if locked.contains(&mentee)
+                        || (current.0 == 0 && options.pair_scores.is_empty())
+                    {
+                        continue;
+                    }
+                    // the change in cost of each possible move or swap
+                    let mut changes: Vec<((i64, i64), Option<NodeId>)> = Vec::new();
+                    if !without.is_empty()
+                        && groups[to].len() < capacity
+                        && fits(to, &groups[to], mentee)
+                    {
+                        changes.push((sub_costs(cost(to, &groups[to], mentee), current), None));
+                    }
+                    for &other in groups[to].iter().filter(|x| !locked.contains(x)) {
+                        let others: Vec<NodeId> =
+                            groups[to].iter().cloned().filter(|x| *x != other).collect();
+                        if fits(from, &without, other) && fits(to, &others, mentee) {
+                            let after =
+                                add_costs(cost(from, &without, other), cost(to, &others, mentee));
+                            let before = add_costs(current, cost(to, &others, other));
+                            changes.push((sub_costs(after, before), Some(other)));
+                        }
+                    }
+                    let best = changes
+                        .into_iter()
+                        .filter(|(change, _)| *change < (0, 0))
+                        .min_by_key(|(change, _)| *change);
+                    if let Some((_, swapped)) = best {
+                        groups[from] = without.into_iter().chain(swapped).collect();
+                        groups[to].retain(|x| Some(*x) != swapped);
+                        groups[to].push(mentee);
+                        improved = true;
+                    }
+                }
             }
         }
-        v
-    };
+        if !improved {
+            break;
+        }
+    }
 
-    let matched_with_remainder = matched_with_remainder
+    let index_to_element = |i: NodeId| vec[i as usize];
+    let matched: Vec<Match<NodeId>> = groups
+        .iter()
+        .enumerate()
+        .map(|(mentor, group)| members(mentor, group))
+        .collect();
+    let imperfect_matches: Vec<T> = repeated_members(&matched, &constraints)
+        .map(index_to_element)
+        .collect();
+    let details: Vec<MatchDetails<T>> = matched
+        .iter()
+        .map(|m| MatchDetails {
+            repeated: m
+                .iter()
+                .cloned()
+                .tuple_combinations()
+                .filter(|(a, b)| met(*a, *b))
+                .map(|(a, b)| (index_to_element(a), index_to_element(b)))
+                .collect(),
+            preferred: m.iter().any(|x| locked.contains(x)),
+            remainder: None,
+            score: m
+                .iter()
+                .cloned()
+                .tuple_combinations()
+                .map(|(a, b)| score(a, b))
+                .sum(),
+        })
+        .collect();
+    let repeated_pairs = details.iter().map(|d| d.repeated.len()).sum();
+    let matched = matched
         .into_iter()
-        .chain(imperfect_match_pairs)
         .map(|m| m.into_iter().map(index_to_element).collect())
         .collect();
+    Ok(Pairing(matched, imperfect_matches, repeated_pairs, details))
+}
+
+/// Adds two (repeats, negated score) costs.
+fn add_costs(a: (i64, i64), b: (i64, i64)) -> (i64, i64) {
+    (a.0 + b.0, a.1 + b.1)
+}
 
-    Ok(Pairing(matched_with_remainder, imperfect_matches))
+/// Subtracts two (repeats, negated score) costs.
+fn sub_costs(a: (i64, i64), b: (i64, i64)) -> (i64, i64) {
+    (a.0 - b.0, a.1 - b.1)
 }
 
-fn build_matching_graph<T: Hash + Eq + Copy>(
-    vec: &Vec<T>,
-    previous_pairings: &Vec<Match<T>>,
-) -> (UnMatrix, HashSet<ConstraintEdge>) {
+/// Derives the seed for a candidate from the base seed. Candidate 0 uses the base seed itself.
+pub fn derive_seed(seed: u64, candidate: usize) -> u64 {
+    if candidate == 0 {
+        return seed;
+    }
+    let mut hasher = DefaultHasher::new();
+    (seed, candidate).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Rates a pairing produced by graph_pair(); higher is better. Repeated pairs matter most, then
+/// placing the remainder with members who have been in few triples, then the soft pair scores.
+pub fn rate_pairing<T: Hash + Eq>(pairing: &Pairing<T>, previous_pairings: &[Match<T>]) -> i64 {
+    const REPEAT_PENALTY: i64 = 1000;
+    const TRIPLE_PENALTY: i64 = 10;
+
+    let Pairing(pairs, _, repeated_pairs, details) = pairing;
+    let previous_triples = |x: &T| {
+        previous_pairings
+            .iter()
+            .filter(|m| m.len() > 2 && m.contains(x))
+            .count() as i64
+    };
+    let triple_members: i64 = pairs
+        .iter()
+        .filter(|m| m.len() > 2)
+        .flatten()
+        .map(previous_triples)
+        .sum();
+    let score: i64 = details.iter().map(|d| d.score).sum();
+    score - REPEAT_PENALTY * *repeated_pairs as i64 - TRIPLE_PENALTY * triple_members
+}
+
+/// Runs graph_pair() with `candidates` seeds derived from `seed`, and returns the best pairing
+/// along with the index of the candidate that produced it and its rating. The same pairing can be
+/// reproduced by calling graph_pair() with `derive_seed(seed, index)`.
+pub fn best_graph_pair<T: Hash + Eq + Copy>(
+    vec: Vec<T>,
+    previous_pairings: &[Match<T>],
+    options: &MatchingOptions<T>,
+    seed: u64,
+    candidates: usize,
+) -> Result<(Pairing<T>, usize, i64)> {
+    let mut best: Option<(Pairing<T>, usize, i64)> = None;
+    let mut first_error = None;
+    for candidate in 0..candidates.max(1) {
+        match graph_pair(
+            vec.clone(),
+            previous_pairings,
+            options,
+            derive_seed(seed, candidate),
+        ) {
+            Ok(pairing) => {
+                let rating = rate_pairing(&pairing, previous_pairings);
+                let is_better = match &best {
+                    Some((_, _, best_rating)) => rating > *best_rating,
+                    None => true,
+                };
+                if is_better {
+                    best = Some((pairing, candidate, rating));
+                }
+            }
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    match (best, first_error) {
+        (Some(best), _) => Ok(best),
+        (None, Some(e)) => Err(e),
+        (None, None) => bail!("No candidates were generated"),
+    }
+}
+
+/// Converts the previous pairings and the options into sets of edges between node indices.
+/// Elements that are not in `vec` are ignored.
+fn build_constraints<T: Hash + Eq + Copy>(
+    vec: &[T],
+    previous_pairings: &[Match<T>],
+    options: &MatchingOptions<T>,
+) -> Constraints {
     let nodes: HashMap<&T, NodeId> = vec
         .iter()
         .enumerate()
@@ -127,78 +588,388 @@ fn build_matching_graph<T: Hash + Eq + Copy>(
         })
         .collect();
 
-    let constraints: HashSet<ConstraintEdge> = previous_pairings
+    let history = previous_pairings
         .iter()
+        .chain(&options.other_history)
         .flat_map(|m| {
             // convert a Match into an iterable of edges of type NodeId
             // each edge has the smaller index first
             m.iter()
                 .flat_map(|u| nodes.get(u)) // filters out constraints not in `vec`
-                .map(|u| u.clone())
+                .copied()
                 .tuple_combinations()
                 .map(ConstraintEdge::new)
         })
-        .collect();
+        .counts();
 
-    (
-        UnMatrix::from_edges(
-            nodes
-                .values()
-                .map(|i| *i)
-                .tuple_combinations()
-                .filter(|e| !constraints.contains(&ConstraintEdge::new(*e))),
-        ),
-        constraints,
+    let to_edges = |pairs: &Vec<(T, T)>| -> HashSet<ConstraintEdge> {
+        pairs
+            .iter()
+            .flat_map(|(a, b)| Some(ConstraintEdge::new((*nodes.get(a)?, *nodes.get(b)?))))
+            .filter(|e| e.lower != e.upper)
+            .collect()
+    };
+
+    let mut previous_triples = vec![0; vec.len()];
+    for m in previous_pairings.iter().filter(|m| m.len() > 2) {
+        for i in m.iter().flat_map(|u| nodes.get(u)) {
+            previous_triples[*i as usize] += 1;
+        }
+    }
+
+    Constraints {
+        history,
+        forbidden: to_edges(&options.never_match),
+        preferred: to_edges(&options.prefer_match),
+        previous_triples,
+    }
+}
+
+/// Builds a graph with exactly `node_count` nodes and the given edges.
+fn graph_with_edges(
+    node_count: usize,
+    edges: impl IntoIterator<Item = (NodeId, NodeId)>,
+) -> UnMatrix {
+    let mut graph = UnMatrix::with_capacity(node_count);
+    for _ in 0..node_count {
+        graph.add_node(());
+    }
+    for (a, b) in edges {
+        graph.add_edge(a.into(), b.into(), ());
+    }
+    graph
+}
+
+/// Builds the graph of allowed matches, excluding the `locked` nodes, previous matches and
+/// forbidden matches.
+fn build_matching_graph(
+    node_count: usize,
+    locked: &HashSet<NodeId>,
+    constraints: &Constraints,
+) -> UnMatrix {
+    graph_with_edges(
+        node_count,
+        (0..node_count as NodeId)
+            .filter(|n| !locked.contains(n))
+            .tuple_combinations()
+            .filter(|e| {
+                let edge = ConstraintEdge::new(*e);
+                !constraints.history.contains_key(&edge) && !constraints.forbidden.contains(&edge)
+            }),
     )
 }
 
-/// Pairs the nodes not in the matching, returning the pairs and a possible remainder
+/// Returns the pairs of a maximum matching of the graph.
+fn max_matching_pairs(graph: &UnMatrix) -> Vec<Match<NodeId>> {
+    maximum_matching(graph)
+        .edges()
+        .map(|(a, b): (NodeIndex<NodeId>, NodeIndex<NodeId>)| {
+            vec![a.index() as NodeId, b.index() as NodeId]
+        })
+        .collect()
+}
+
+/// Finds a maximum matching using only the given edges, skipping any edge touching `excluded`.
+fn match_edges(
+    node_count: usize,
+    excluded: &HashSet<NodeId>,
+    edges: impl Iterator<Item = (NodeId, NodeId)>,
+) -> Vec<Match<NodeId>> {
+    max_matching_pairs(&graph_with_edges(
+        node_count,
+        edges.filter(|(a, b)| !excluded.contains(a) && !excluded.contains(b)),
+    ))
+}
+
+/// Swaps partners between pairs while that increases the total score. Swaps never create a
+/// previous or forbidden match, so the quality of the matching is not reduced.
+fn improve_matching(
+    matched: &mut [Match<NodeId>],
+    constraints: &Constraints,
+    score: &impl Fn(NodeId, NodeId) -> i64,
+) {
+    // bounds the running time; in practice this converges after a few passes
+    const MAX_PASSES: usize = 20;
+
+    let allowed = |a: NodeId, b: NodeId| {
+        let edge = ConstraintEdge::new((a, b));
+        !constraints.history.contains_key(&edge) && !constraints.forbidden.contains(&edge)
+    };
+
+    for _ in 0..MAX_PASSES {
+        let mut improved = false;
+        for i in 0..matched.len() {
+            for j in (i + 1)..matched.len() {
+                let (&[a, b], &[c, d]) = (&matched[i][..], &matched[j][..]) else {
+                    continue;
+                };
+                let current = score(a, b) + score(c, d);
+                let best = [((a, c), (b, d)), ((a, d), (b, c))]
+                    .into_iter()
+                    .filter(|((w, x), (y, z))| allowed(*w, *x) && allowed(*y, *z))
+                    .map(|((w, x), (y, z))| (score(w, x) + score(y, z), (w, x), (y, z)))
+                    .max_by_key(|(total, _, _)| *total);
+                if let Some((total, (w, x), (y, z))) = best {
+                    if total > current {
+                        matched[i] = vec![w, x];
+                        matched[j] = vec![y, z];
+                        improved = true;
+                    }
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+}
+
+/// If there is an odd number of nodes to match, chooses the node that will be added to a triple.
+/// Nodes that have been in the fewest previous triples are preferred, as long as leaving them out
+/// doesn't reduce the number of nodes that can be matched with someone new. This rotates who ends
+/// up in a triple between rounds, rather than depending only on the shuffle order.
+fn choose_remainder(
+    node_count: usize,
+    locked: &HashSet<NodeId>,
+    constraints: &Constraints,
+) -> Option<NodeId> {
+    // bounds the number of extra matchings computed
+    const MAX_CANDIDATES: usize = 16;
+
+    let free: Vec<NodeId> = (0..node_count as NodeId)
+        .filter(|n| !locked.contains(n))
+        .collect();
+    if free.len() % 2 != 1 {
+        return None;
+    }
+
+    let baseline = max_matching_pairs(&build_matching_graph(node_count, locked, constraints));
+    let baseline_matched: HashSet<NodeId> = baseline.iter().flatten().cloned().collect();
+    let by_triples = |n: &NodeId| (constraints.previous_triples[*n as usize], *n);
+
+    free.iter()
+        .cloned()
+        .sorted_by_key(by_triples)
+        .take(MAX_CANDIDATES)
+        .find(|candidate| {
+            let mut excluded = locked.clone();
+            excluded.insert(*candidate);
+            let pairs =
+                max_matching_pairs(&build_matching_graph(node_count, &excluded, constraints));
+            pairs.len() == baseline.len()
+        })
+        .or_else(|| {
+            // otherwise, one of the nodes left out of the baseline matching
+            free.iter()
+                .cloned()
+                .filter(|n| !baseline_matched.contains(n))
+                .min_by_key(by_triples)
+        })
+}
+
+/// Pairs the nodes not in the matching (other than `reserved`), returning the pairs and a
+/// possible remainder. These pairs may repeat previous matches, but never contain a forbidden
+/// match.
 fn pair_unmatched(
-    graph: &UnMatrix,
-    matching: &Matching<&UnMatrix>,
-) -> (Vec<Match<NodeId>>, Option<NodeId>) {
-    let unmatched: Vec<NodeId> = (0..(graph.node_count() as NodeId))
-        .filter(|n| !matching.contains_node((*n).into()))
+    node_count: usize,
+    matched: &[Match<NodeId>],
+    reserved: Option<NodeId>,
+    constraints: &Constraints,
+) -> Result<(Vec<Match<NodeId>>, Option<NodeId>)> {
+    let already_matched: HashSet<NodeId> =
+        matched.iter().flatten().cloned().chain(reserved).collect();
+    let unmatched: Vec<NodeId> = (0..node_count as NodeId)
+        .filter(|n| !already_matched.contains(n))
         .collect();
-    let unmatched_pairs = unmatched.chunks_exact(2);
-    assert!(
-        unmatched_pairs.remainder().len() <= 1,
-        "got more than 1 remainder"
+
+    let unmatched_pairs = match_edges(
+        node_count,
+        &already_matched,
+        unmatched
+            .iter()
+            .cloned()
+            .tuple_combinations()
+            .filter(|e| !constraints.forbidden.contains(&ConstraintEdge::new(*e))),
     );
-    (
-        unmatched_pairs.clone().map(|p| p.to_vec()).collect(),
-        unmatched_pairs.remainder().first().cloned(),
-    )
+    let paired: HashSet<NodeId> = unmatched_pairs.iter().flatten().cloned().collect();
+    let leftover: Vec<NodeId> = unmatched
+        .into_iter()
+        .filter(|n| !paired.contains(n))
+        .collect();
+    ensure!(
+        leftover.len() <= 1,
+        "Unable to pair {} members without breaking a never-match rule",
+        leftover.len()
+    );
+    Ok((unmatched_pairs, leftover.first().cloned()))
 }
 
-/// Returns a new pairing with the remainder added to the most compatible Match,
-/// and returns the remainder score.
+/// Returns the total number of times the members of `group` have been matched with each other.
+fn repeat_count(group: &[NodeId], constraints: &Constraints) -> usize {
+    group
+        .iter()
+        .tuple_combinations()
+        .flat_map(|(a, b)| constraints.history.get(&ConstraintEdge::new((*a, *b))))
+        .sum()
+}
+
+/// Every way to split `nodes` (an even number of them) into pairs.
+fn arrangements(nodes: &[NodeId]) -> Vec<Vec<(NodeId, NodeId)>> {
+    let Some((&first, rest)) = nodes.split_first() else {
+        return vec![Vec::new()];
+    };
+    (0..rest.len())
+        .flat_map(|i| {
+            let mut others = rest.to_vec();
+            let partner = others.remove(i);
+            arrangements(&others).into_iter().map(move |mut pairs| {
+                pairs.push((first, partner));
+                pairs
+            })
+        })
+        .collect()
+}
+
+/// Reduces the total number of repeats with a local search: each pair that repeats a previous
+/// match tries re-pairing its members with those of one other pair, and then with those of two
+/// of a few others, keeping the first change that lowers the total. This spreads unavoidable
+/// repeats onto members who have met the fewest times, and never creates a forbidden match or
+/// changes a preferred pair. It is a heuristic, so the result may still have more repeats than
+/// the best possible pairing.
+fn reduce_repeats(
+    pairs: &mut [Match<NodeId>],
+    locked: &HashSet<NodeId>,
+    constraints: &Constraints,
+) {
+    // bounds the running time; in practice this converges after a few passes
+    const MAX_PASSES: usize = 20;
+    // bounds the other pairs each pair tries three-pair moves with, since each three pairs have
+    // 15 arrangements
+    const MAX_THREE_PAIR_PARTNERS: usize = 12;
+
+    let allowed =
+        |a: NodeId, b: NodeId| !constraints.forbidden.contains(&ConstraintEdge::new((a, b)));
+    let cost = |a: NodeId, b: NodeId| repeat_count(&[a, b], constraints);
+
+    // the cheapest way to re-pair the members of the pairs at `indices`, if it has fewer repeats
+    let improvement = |pairs: &[Match<NodeId>], indices: &[usize]| {
+        let members: Vec<NodeId> = indices.iter().flat_map(|&i| pairs[i].clone()).collect();
+        if members.len() != 2 * indices.len() || members.iter().any(|n| locked.contains(n)) {
+            return None;
+        }
+        let current: usize = indices
+            .iter()
+            .map(|&i| repeat_count(&pairs[i], constraints))
+            .sum();
+        arrangements(&members)
+            .into_iter()
+            .filter(|a| a.iter().all(|&(x, y)| allowed(x, y)))
+            .map(|a| (a.iter().map(|&(x, y)| cost(x, y)).sum::<usize>(), a))
+            .filter(|(total, _)| *total < current)
+            .min_by_key(|(total, _)| *total)
+            .map(|(_, a)| a)
+    };
+
+    for _ in 0..MAX_PASSES {
+        let mut improved = false;
+        for i in 0..pairs.len() {
+            if repeat_count(&pairs[i], constraints) == 0 {
+                continue;
+            }
+            let others = || (0..pairs.len()).filter(move |&j| j != i);
+            // trying every two other pairs would be cubic in the number of pairs, so three-pair
+            // moves are only tried with a few others, starting with those that repeat too
+            let partners: Vec<usize> = others()
+                .sorted_by_key(|&j| repeat_count(&pairs[j], constraints) == 0)
+                .take(MAX_THREE_PAIR_PARTNERS)
+                .collect();
+            let groups = others().map(|j| vec![i, j]).chain(
+                partners
+                    .iter()
+                    .tuple_combinations()
+                    .map(|(&j, &k)| vec![i, j, k]),
+            );
+            for indices in groups {
+                if let Some(arrangement) = improvement(pairs, &indices) {
+                    for (&index, (a, b)) in indices.iter().zip(arrangement) {
+                        pairs[index] = vec![a, b];
+                    }
+                    improved = true;
+                    break;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+}
+
+/// The members of each Match who have been matched before with someone else in it. A member of a
+/// triple who is new to both others is not included, even if the other two have met.
+fn repeated_members<'a>(
+    groups: &'a [Match<NodeId>],
+    constraints: &'a Constraints,
+) -> impl Iterator<Item = NodeId> + 'a {
+    groups.iter().flat_map(move |m| {
+        m.iter().cloned().filter(move |&a| {
+            m.iter().any(|&b| {
+                a != b
+                    && constraints
+                        .history
+                        .contains_key(&ConstraintEdge::new((a, b)))
+            })
+        })
+    })
+}
+
+/// Returns a new pairing with the remainder added to the Match whose members it has been matched
+/// with the fewest times before. Ties are broken by preferring Matches whose members have been
+/// in fewer previous triples, and then by the highest pair score.
 fn add_remainder_to_pairing(
     mut matched: Vec<Match<NodeId>>,
     remainder: Option<NodeId>,
-    constraints: &HashSet<ConstraintEdge>,
-) -> Result<(Vec<Match<NodeId>>, usize)> {
+    constraints: &Constraints,
+    score: &impl Fn(NodeId, NodeId) -> i64,
+) -> Result<Vec<Match<NodeId>>> {
     match remainder {
         Some(remainder) => {
-            let (remainder_match_score, remainder_match) = matched
+            let remainder_match = matched
                 .iter_mut()
+                .filter(|v| {
+                    !v.iter().any(|x| {
+                        constraints
+                            .forbidden
+                            .contains(&ConstraintEdge::new((*x, remainder)))
+                    })
+                })
                 .map(|v| {
                     let count = v
                         .iter()
-                        .filter(|x| constraints.contains(&ConstraintEdge::new((**x, remainder))))
+                        .filter(|x| {
+                            constraints
+                                .history
+                                .contains_key(&ConstraintEdge::new((**x, remainder)))
+                        })
                         .count();
-                    (count, v)
+                    let triples: usize = v
+                        .iter()
+                        .map(|x| constraints.previous_triples[*x as usize])
+                        .sum();
+                    let pair_score: i64 = v.iter().map(|x| score(*x, remainder)).sum();
+                    (count, triples, -pair_score, v)
                 })
-                .min()
-                .map(|(score, m)| (score, m))
-                .context("Unexpectedly encountered empty matched iterable")?;
+                .min_by_key(|(count, triples, neg_score, v)| {
+                    (*count, *triples, *neg_score, v.to_vec())
+                })
+                .map(|(_, _, _, v)| v)
+                .context(
+                    "Unable to place the remaining member without breaking a never-match rule",
+                )?;
 
             remainder_match.push(remainder);
-
-            // the remainder match score is the number of people in `remainder_match` that `remainder`
-            // has constraints against it. lower is better.
-            Ok((matched, remainder_match_score))
+            Ok(matched)
         }
-        _ => Ok((matched, 0)),
+        _ => Ok(matched),
     }
 }
@@ -0,0 +1,191 @@
+use crate::guild_api::GuildMember;
+use serenity::all::{ChunkGuildFilter, Context, FullEvent, GuildId, RoleId, UserId};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Mutex;
+
+/// The members of one guild, indexed by role.
+#[derive(Debug, Default)]
+struct IndexedGuild {
+    members: HashMap<UserId, GuildMember>,
+    by_role: HashMap<RoleId, BTreeSet<UserId>>,
+    /// The chunks of members received so far, while the guild is not complete
+    received_chunks: HashSet<u32>,
+    /// Whether every member of the guild has been received from the gateway
+    complete: bool,
+}
+
+impl IndexedGuild {
+    fn insert(&mut self, member: GuildMember) {
+        self.remove(member.id);
+        for role in &member.roles {
+            self.by_role.entry(*role).or_default().insert(member.id);
+        }
+        self.members.insert(member.id, member);
+    }
+
+    fn remove(&mut self, user_id: UserId) {
+        if let Some(old) = self.members.remove(&user_id) {
+            for role in &old.roles {
+                if let Some(members) = self.by_role.get_mut(role) {
+                    members.remove(&user_id);
+                }
+            }
+        }
+    }
+}
+
+/// An in-memory index from roles to guild members, kept up to date by gateway events. This needs
+/// the privileged GUILD_MEMBERS intent, and replaces paging through the member list with the REST
+/// API on every command.
+#[derive(Debug, Default)]
+pub struct MemberIndex {
+    guilds: Mutex<HashMap<GuildId, IndexedGuild>>,
+}
+
+impl MemberIndex {
+    /// Returns the members of the guild with the role, ordered by ID (like the REST API), or None
+    /// if the guild's members have not all been received yet.
+    pub fn members_with_role(
+        &self,
+        guild_id: GuildId,
+        role_id: RoleId,
+    ) -> Option<Vec<GuildMember>> {
+        self.with_guilds(|guilds| {
+            let guild = guilds.get(&guild_id).filter(|g| g.complete)?;
+            Some(
+                guild
+                    .by_role
+                    .get(&role_id)
+                    .into_iter()
+                    .flatten()
+                    .map(|id| guild.members[id].clone())
+                    .collect(),
+            )
+        })
+    }
+
+    fn with_guilds<R>(&self, f: impl FnOnce(&mut HashMap<GuildId, IndexedGuild>) -> R) -> R {
+        f(&mut self
+            .guilds
+            .lock()
+            .expect("index lock should not be poisoned"))
+    }
+
+    /// Starts indexing a guild from the members it was sent with. Returns whether the guild has
+    /// more members, which must be requested in chunks.
+    pub fn guild_created(
+        &self,
+        guild_id: GuildId,
+        members: impl IntoIterator<Item = GuildMember>,
+        member_count: u64,
+    ) -> bool {
+        let mut indexed = IndexedGuild::default();
+        for member in members {
+            indexed.insert(member);
+        }
+        indexed.complete = indexed.members.len() as u64 >= member_count;
+        let needs_chunks = !indexed.complete;
+        self.with_guilds(|guilds| guilds.insert(guild_id, indexed));
+        needs_chunks
+    }
+
+    /// Stops indexing a guild the bot has left or lost access to.
+    pub fn guild_deleted(&self, guild_id: GuildId) {
+        self.with_guilds(|guilds| guilds.remove(&guild_id));
+    }
+
+    /// Adds one chunk of a guild's members. Chunks can arrive in any order, so the guild is
+    /// complete once every chunk index has been received.
+    pub fn members_chunk(
+        &self,
+        guild_id: GuildId,
+        members: impl IntoIterator<Item = GuildMember>,
+        chunk_index: u32,
+        chunk_count: u32,
+    ) {
+        self.with_guilds(|guilds| {
+            let indexed = guilds.entry(guild_id).or_default();
+            for member in members {
+                indexed.insert(member);
+            }
+            indexed.received_chunks.insert(chunk_index);
+            if (0..chunk_count).all(|i| indexed.received_chunks.contains(&i)) {
+                indexed.complete = true;
+                indexed.received_chunks.clear();
+            }
+        });
+    }
+
+    /// Adds a member who joined, or replaces a member whose roles changed.
+    pub fn member_updated(&self, guild_id: GuildId, member: GuildMember) {
+        self.with_guilds(|guilds| {
+            if let Some(indexed) = guilds.get_mut(&guild_id) {
+                indexed.insert(member);
+            }
+        });
+    }
+
+    /// Removes a member who left the guild.
+    pub fn member_removed(&self, guild_id: GuildId, user_id: UserId) {
+        self.with_guilds(|guilds| {
+            if let Some(indexed) = guilds.get_mut(&guild_id) {
+                indexed.remove(user_id);
+            }
+        });
+    }
+
+    /// Removes a deleted role from every member.
+    pub fn role_deleted(&self, guild_id: GuildId, role_id: RoleId) {
+        self.with_guilds(|guilds| {
+            if let Some(indexed) = guilds.get_mut(&guild_id) {
+                indexed.by_role.remove(&role_id);
+                for member in indexed.members.values_mut() {
+                    member.roles.retain(|r| *r != role_id);
+                }
+            }
+        });
+    }
+
+    /// Updates the index from a gateway event.
+    pub fn handle_event(&self, ctx: &Context, event: &FullEvent) {
+        match event {
+            FullEvent::GuildCreate { guild, .. } => {
+                let members = guild.members.values().map(GuildMember::from);
+                // large guilds only include some members, so request the rest in chunks
+                if self.guild_created(guild.id, members, guild.member_count) {
+                    ctx.shard
+                        .chunk_guild(guild.id, None, false, ChunkGuildFilter::None, None);
+                }
+            }
+            FullEvent::GuildDelete { incomplete, .. } => self.guild_deleted(incomplete.id),
+            FullEvent::GuildMembersChunk { chunk } => self.members_chunk(
+                chunk.guild_id,
+                chunk.members.values().map(GuildMember::from),
+                chunk.chunk_index,
+                chunk.chunk_count,
+            ),
+            FullEvent::GuildMemberAddition { new_member } => {
+                self.member_updated(new_member.guild_id, new_member.into())
+            }
+            FullEvent::GuildMemberUpdate { event, .. } => self.member_updated(
+                event.guild_id,
+                GuildMember {
+                    id: event.user.id,
+                    roles: event.roles.clone(),
+                    bot: event.user.bot,
+                    pending: event.pending,
+                    joined_at: Some(event.joined_at),
+                },
+            ),
+            FullEvent::GuildMemberRemoval { guild_id, user, .. } => {
+                self.member_removed(*guild_id, user.id)
+            }
+            FullEvent::GuildRoleDelete {
+                guild_id,
+                removed_role_id,
+                ..
+            } => self.role_deleted(*guild_id, *removed_role_id),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, Opts, Registry, TextEncoder,
+};
+use serenity::all::FullEvent;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Prometheus metrics for the bot, served from the `/metrics` endpoint.
+#[derive(Debug)]
+pub struct Metrics {
+    registry: Registry,
+    /// Commands run, labelled by command name
+    pub commands: IntCounterVec,
+    /// Rounds sent with /send_pairing
+    pub rounds_sent: IntCounter,
+    /// Direct messages sent to members, labelled by whether they were delivered
+    pub direct_messages: IntCounterVec,
+    /// How long it takes to find the pairing for a round
+    pub matching_duration: Histogram,
+    /// The number of members in each round that was sent
+    pub round_participants: Histogram,
+    /// Times the connection to the discord gateway has been resumed or re-established
+    pub gateway_reconnects: IntCounter,
+    /// Whether the gateway has connected before, so the next Ready event is a reconnect
+    connected: AtomicBool,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let registry = Registry::new_custom(Some("matchy".to_owned()), None)
+            .expect("the metrics prefix should be valid");
+        let commands = IntCounterVec::new(
+            Opts::new("commands_total", "Commands run, by command"),
+            &["command"],
+        )
+        .expect("metric options should be valid");
+        let rounds_sent = IntCounter::new("rounds_sent_total", "Rounds sent with /send_pairing")
+            .expect("metric options should be valid");
+        let direct_messages = IntCounterVec::new(
+            Opts::new(
+                "direct_messages_total",
+                "Direct messages sent to members, by outcome",
+            ),
+            &["outcome"],
+        )
+        .expect("metric options should be valid");
+        let matching_duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "matching_duration_seconds",
+                "Time taken to find the pairing for a round",
+            )
+            .buckets(prometheus::exponential_buckets(0.001, 4.0, 9).expect("buckets are valid")),
+        )
+        .expect("metric options should be valid");
+        let round_participants = Histogram::with_opts(
+            HistogramOpts::new("round_participants", "Members in each round that was sent")
+                .buckets(prometheus::exponential_buckets(2.0, 2.0, 10).expect("buckets are valid")),
+        )
+        .expect("metric options should be valid");
+        let gateway_reconnects = IntCounter::new(
+            "gateway_reconnects_total",
+            "Times the gateway connection was resumed or re-established",
+        )
+        .expect("metric options should be valid");
+
+        for collector in [
+            Box::new(commands.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(rounds_sent.clone()),
+            Box::new(direct_messages.clone()),
+            Box::new(matching_duration.clone()),
+            Box::new(round_participants.clone()),
+            Box::new(gateway_reconnects.clone()),
+        ] {
+            registry
+                .register(collector)
+                .expect("metric names should be unique");
+        }
+
+        Metrics {
+            registry,
+            commands,
+            rounds_sent,
+            direct_messages,
+            matching_duration,
+            round_participants,
+            gateway_reconnects,
+            connected: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Metrics {
+    /// Records a direct message that was or was not delivered.
+    pub fn record_direct_message(&self, delivered: bool) {
+        let outcome = if delivered { "delivered" } else { "failed" };
+        self.direct_messages.with_label_values(&[outcome]).inc();
+    }
+
+    /// Counts gateway reconnects from gateway events.
+    pub fn handle_event(&self, event: &FullEvent) {
+        let reconnected = match event {
+            FullEvent::Ready { .. } => self.connected.swap(true, Ordering::Relaxed),
+            FullEvent::Resume { .. } => true,
+            _ => false,
+        };
+        if reconnected {
+            self.gateway_reconnects.inc();
+        }
+    }
+
+    /// Returns the metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("encoding metrics to a buffer should not fail");
+        String::from_utf8(buffer).expect("the text format should be UTF-8")
+    }
+}
@@ -0,0 +1,77 @@
+use crate::audit::audited;
+use crate::storage::PermissionLevel;
+use crate::types::Context;
+use anyhow::{bail, Context as _, Result};
+use serenity::all::{Permissions, RoleId};
+
+/// Whether a member with the roles (and, if `administrator`, the ADMINISTRATOR permission) has the
+/// level, given the guild's organizer roles.
+pub fn has_level(
+    level: PermissionLevel,
+    administrator: bool,
+    member_roles: &[RoleId],
+    organizer_roles: &[(RoleId, PermissionLevel)],
+) -> bool {
+    administrator
+        || organizer_roles
+            .iter()
+            .any(|(role, granted)| *granted >= level && member_roles.contains(role))
+}
+
+/// Whether the author of the command has the level.
+pub async fn author_has_level(ctx: Context<'_>, level: PermissionLevel) -> Result<bool> {
+    let guild_id = ctx
+        .guild_id()
+        .context("This command must be called from a guild (server).")?;
+    let member = ctx
+        .author_member()
+        .await
+        .context("Unable to fetch your roles in this server")?;
+    // interactions include the member's permissions, but prefix commands need the cached guild
+    let permissions = match member.permissions {
+        Some(permissions) => permissions,
+        None => ctx
+            .guild()
+            .map(|guild| guild.member_permissions(&member))
+            .unwrap_or_else(Permissions::empty),
+    };
+    let organizer_roles = ctx
+        .data()
+        .store
+        .read(guild_id, |data| data.settings.organizer_roles.clone());
+    Ok(has_level(
+        level,
+        permissions.administrator(),
+        &member.roles,
+        &organizer_roles,
+    ))
+}
+
+async fn require_level(ctx: Context<'_>, level: PermissionLevel) -> Result<bool> {
+    if !author_has_level(ctx, level).await? {
+        bail!(
+            "You need ADMINISTRATOR or a matchy organizer role with the `{level}` permission to \
+            use this command."
+        );
+    }
+    // anything above viewing changes the guild, so it's recorded in the audit channel
+    if level > PermissionLevel::View {
+        audited(ctx).await?;
+    }
+    Ok(true)
+}
+
+/// A command check for the `view` level.
+pub async fn require_view(ctx: Context<'_>) -> Result<bool> {
+    require_level(ctx, PermissionLevel::View).await
+}
+
+/// A command check for the `send` level.
+pub async fn require_send(ctx: Context<'_>) -> Result<bool> {
+    require_level(ctx, PermissionLevel::Send).await
+}
+
+/// A command check for the `configure` level.
+pub async fn require_configure(ctx: Context<'_>) -> Result<bool> {
+    require_level(ctx, PermissionLevel::Configure).await
+}
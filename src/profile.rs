@@ -0,0 +1,114 @@
+use crate::availability::availability;
+use crate::helpers::{command_span, handle_error};
+use crate::matching::PairScore;
+use crate::storage::Profile;
+use crate::types::{Context, Data};
+use anyhow::{Context as _, Error, Result};
+use itertools::Itertools;
+use poise::Modal;
+use serenity::all::UserId;
+use std::collections::{HashMap, HashSet};
+use tracing::Instrument;
+
+/// The weight of each shared interest when matching
+const SHARED_INTEREST_WEIGHT: i64 = 1;
+
+/// The matchy meetups profile questionnaire.
+#[derive(Debug, Modal)]
+#[name = "Matchy Meetups profile"]
+struct ProfileModal {
+    #[name = "Interests (comma separated)"]
+    #[placeholder = "hiking, rust, board games"]
+    #[max_length = 300]
+    interests: String,
+    #[name = "When are you usually free?"]
+    #[placeholder = "weekday evenings, saturday mornings"]
+    #[max_length = 200]
+    availability: Option<String>,
+    #[name = "A short intro for your partners"]
+    #[paragraph]
+    #[max_length = 500]
+    intro: Option<String>,
+}
+
+/// Splits a comma separated list of interests into normalized interests.
+fn parse_interests(interests: &str) -> Vec<String> {
+    interests
+        .split(',')
+        .map(|i| i.trim().to_lowercase())
+        .filter(|i| !i.is_empty())
+        .unique()
+        .collect()
+}
+
+/// Creates a score function that rewards matching members who share interests.
+pub fn interest_score(profiles: &HashMap<UserId, Profile>) -> PairScore<UserId> {
+    let interests: HashMap<UserId, HashSet<String>> = profiles
+        .iter()
+        .map(|(id, p)| (*id, p.interests.iter().cloned().collect()))
+        .collect();
+    Box::new(move |a, b| match (interests.get(a), interests.get(b)) {
+        (Some(a), Some(b)) => a.intersection(b).count() as i64 * SHARED_INTEREST_WEIGHT,
+        _ => 0,
+    })
+}
+
+/// Formats a profile to introduce a member to their partners, or None if it is empty.
+pub fn format_profile(profile: &Profile) -> Option<String> {
+    let lines: Vec<String> = [
+        ("Intro", profile.intro.clone()),
+        ("Interests", profile.interests.join(", ")),
+        ("Usually free", profile.availability.clone()),
+    ]
+    .into_iter()
+    .filter(|(_, v)| !v.is_empty())
+    .map(|(k, v)| format!("> **{k}:** {v}"))
+    .collect();
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+async fn handle_profile(ctx: poise::ApplicationContext<'_, Data, Error>) -> Result<String> {
+    let guild_id = ctx
+        .guild_id()
+        .context("This command must be called from a guild (server).")?;
+    let user_id = ctx.author().id;
+    let defaults = ctx.data().store.read(guild_id, |data| {
+        data.profiles.get(&user_id).map(|p| ProfileModal {
+            interests: p.interests.join(", "),
+            availability: Some(p.availability.clone()),
+            intro: Some(p.intro.clone()),
+        })
+    });
+    let Some(response) = poise::execute_modal(ctx, defaults, None).await? else {
+        return Ok("Your profile was not changed.".to_owned());
+    };
+    ctx.data().store.update(guild_id, |data| {
+        let profile = data.profiles.entry(user_id).or_default();
+        profile.interests = parse_interests(&response.interests);
+        profile.availability = response.availability.unwrap_or_default().trim().to_owned();
+        profile.intro = response.intro.unwrap_or_default().trim().to_owned();
+    })?;
+    Ok("Your matchy meetups profile has been saved!".to_owned())
+}
+
+/// Matchy meetups commands for members
+#[poise::command(
+    slash_command,
+    ephemeral,
+    subcommands("profile", "availability"),
+    on_error = "handle_error"
+)]
+pub async fn matchy(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Set your interests and a short intro, which are shared with your partners
+#[poise::command(slash_command, ephemeral, guild_only, on_error = "handle_error")]
+async fn profile(ctx: poise::ApplicationContext<'_, Data, Error>) -> Result<()> {
+    let resp = handle_profile(ctx)
+        .instrument(command_span(ctx.into()))
+        .await
+        .unwrap_or_else(|e| format!("Error: {}", e));
+    ctx.say(resp).await?;
+    Ok(())
+}
@@ -0,0 +1,265 @@
+use crate::config::{HISTORY_CHANNEL_NAME, NOTIFICATION_CHANNEL_NAME};
+use crate::guild_api::{GuildApi, SerenityGuild};
+use crate::helpers::{command_span, handle_error, respond};
+use crate::permissions::{require_configure, require_view};
+use crate::storage::ProgramData;
+use crate::types::{Context, Data};
+use crate::ROLE_NAME;
+use anyhow::{bail, Result};
+use itertools::Itertools;
+use serenity::all::{ChannelId, GuildChannel, Role, RoleId};
+use tracing::Instrument;
+
+/// The name of the program every guild has, which uses ROLE_NAME and the default channel names
+/// until it is configured.
+pub const DEFAULT_PROGRAM: &str = "matchy-meetups";
+
+/// The announcement posted when a round is sent. `{role}` is replaced with a mention of the
+/// program's role, and `{pairs}` with the pairs.
+pub const DEFAULT_NOTIFICATION_TEMPLATE: &str =
+    "Hey {role}, here are the pairings for the next round of matchy meetups!\n\n{pairs}";
+
+/// The message sent to each member of a round. `{partners}` is replaced with the member's
+/// partners, and `{details}` with their partners' profiles and when they're all free.
+pub const DEFAULT_DM_TEMPLATE: &str = "Hey, thanks for joining ICSSC's Matchy Meetups. Your \
+    pairing for this round is here! Please take this opportunity to reach out to them and \
+    schedule some time to hang out in the next two weeks. Don't forget to send pics to \
+    https://discord.com/channels/760915616793755669/1199228930222194779 while you're there, and I \
+    hope you enjoy!\n\t\t\t\t\t\t\t \\- Jeffrey \n\n\n**Your pairing is with:** \
+    {partners}{details}\n\n_(responses here will not be seen; please message Jeffrey directly if \
+    you have any questions)_";
+
+/// A program and its stored data.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub name: String,
+    pub data: ProgramData,
+}
+
+impl Program {
+    /// Loads the program with the name from the store.
+    pub fn load(api: &impl GuildApi, data: &Data, name: &str) -> Result<Self> {
+        let program = data
+            .store
+            .read_program(api.guild_id(), name, ProgramData::clone)?;
+        Ok(Program {
+            name: name.to_owned(),
+            data: program,
+        })
+    }
+
+    /// Returns the role of the members in the program.
+    pub fn role(&self, api: &impl GuildApi) -> Result<RoleId> {
+        if let Some(role) = self.data.role {
+            return Ok(role);
+        }
+        match api.role_by_name(ROLE_NAME) {
+            Some(role) => Ok(role),
+            None => bail!("Could not find a role with name `{ROLE_NAME}`"),
+        }
+    }
+
+    /// Returns the channel rounds are announced in.
+    pub async fn notification_channel(&self, api: &impl GuildApi) -> Result<ChannelId> {
+        if let Some(channel) = self.data.notification_channel {
+            return Ok(channel);
+        }
+        match api.find_channel(NOTIFICATION_CHANNEL_NAME).await? {
+            Some(channel) => Ok(channel),
+            None => bail!("Could not find notification channel"),
+        }
+    }
+
+    /// Returns the channel rounds are recorded in.
+    pub async fn history_channel(&self, api: &impl GuildApi) -> Result<ChannelId> {
+        if let Some(channel) = self.data.history_channel {
+            return Ok(channel);
+        }
+        match api.find_channel(HISTORY_CHANNEL_NAME).await? {
+            Some(channel) => Ok(channel),
+            None => bail!("Could not find history channel"),
+        }
+    }
+
+    /// Formats the announcement for a round.
+    pub fn notification(&self, role: RoleId, pairs: &str) -> String {
+        self.data
+            .settings
+            .notification_template
+            .as_deref()
+            .unwrap_or(DEFAULT_NOTIFICATION_TEMPLATE)
+            .replace("{role}", &format!("<@&{role}>"))
+            .replace("{pairs}", pairs)
+    }
+
+    /// Formats the message sent to a member of a round.
+    pub fn direct_message(&self, partners: &str, details: &str) -> String {
+        self.data
+            .settings
+            .dm_template
+            .as_deref()
+            .unwrap_or(DEFAULT_DM_TEMPLATE)
+            .replace("{partners}", partners)
+            .replace("{details}", details)
+    }
+}
+
+/// Suggests the names of the guild's programs for a `program` argument.
+pub async fn autocomplete_program(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Vec::new();
+    };
+    let partial = partial.to_lowercase();
+    ctx.data()
+        .store
+        .read(guild_id, |data| data.program_names())
+        .into_iter()
+        .filter(|name| name.to_lowercase().contains(&partial))
+        .collect()
+}
+
+async fn handle_create_program(
+    ctx: Context<'_>,
+    name: &str,
+    role: &Role,
+    notification_channel: ChannelId,
+    history_channel: ChannelId,
+) -> Result<String> {
+    let api = SerenityGuild::new(ctx)?;
+    let name = name.trim();
+    let program = ProgramData {
+        role: Some(role.id),
+        notification_channel: Some(notification_channel),
+        history_channel: Some(history_channel),
+        ..ProgramData::default()
+    };
+    let named_history_channel = api.find_channel(HISTORY_CHANNEL_NAME).await?;
+    ctx.data().store.update(api.guild_id(), |data| {
+        data.create_program(name, program, named_history_channel)
+    })??;
+    Ok(format!(
+        "Created `{name}` for members with <@&{}>. Rounds will be announced in \
+        <#{notification_channel}> and recorded in <#{history_channel}>.",
+        role.id
+    ))
+}
+
+async fn handle_remove_program(ctx: Context<'_>, name: &str) -> Result<String> {
+    let api = SerenityGuild::new(ctx)?;
+    ctx.data()
+        .store
+        .update(api.guild_id(), |data| data.remove_program(name))??;
+    Ok(format!(
+        "Removed `{name}` and its rules, attributes and settings. Its history channel was not \
+        changed."
+    ))
+}
+
+async fn handle_list_programs(ctx: Context<'_>) -> Result<String> {
+    let api = SerenityGuild::new(ctx)?;
+    let names = ctx
+        .data()
+        .store
+        .read(api.guild_id(), |data| data.program_names());
+    let mut lines = Vec::with_capacity(names.len());
+    for name in names {
+        let program = Program::load(&api, ctx.data(), &name)?;
+        // the default program may not have its role or channels yet
+        let role = program
+            .role(&api)
+            .map_or("no role".to_owned(), |r| format!("<@&{r}>"));
+        let notification_channel = match program.notification_channel(&api).await {
+            Ok(channel) => format!("<#{channel}>"),
+            Err(_) => "no notification channel".to_owned(),
+        };
+        let history_channel = match program.history_channel(&api).await {
+            Ok(channel) => format!("<#{channel}>"),
+            Err(_) => "no history channel".to_owned(),
+        };
+        lines.push(format!(
+            "`{name}`: {role}, announced in {notification_channel}, recorded in \
+            {history_channel}, every {} days",
+            program.data.settings.cadence_days
+        ));
+    }
+    Ok(lines.into_iter().join("\n"))
+}
+
+/// Manage the programs in this server, each with its own members, channels and history
+#[poise::command(
+    slash_command,
+    hide_in_help,
+    ephemeral,
+    check = "require_view",
+    subcommands("create", "remove", "list"),
+    on_error = "handle_error"
+)]
+pub async fn matchy_programs(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Create a program for the members with a role
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_configure",
+    on_error = "handle_error"
+)]
+async fn create(
+    ctx: Context<'_>,
+    #[description = "The name of the program."] name: String,
+    #[description = "The role of the members in the program."] role: Role,
+    #[description = "The channel rounds are announced in."]
+    #[channel_types("Text")]
+    notification_channel: GuildChannel,
+    #[description = "The channel rounds are recorded in."]
+    #[channel_types("Text")]
+    history_channel: GuildChannel,
+) -> Result<()> {
+    let resp = handle_create_program(
+        ctx,
+        &name,
+        &role,
+        notification_channel.id,
+        history_channel.id,
+    )
+    .instrument(command_span(ctx))
+    .await;
+    respond(ctx, resp).await
+}
+
+/// Remove a program and its rules, attributes and settings
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_configure",
+    on_error = "handle_error"
+)]
+async fn remove(
+    ctx: Context<'_>,
+    #[description = "The program to remove."]
+    #[autocomplete = "autocomplete_program"]
+    name: String,
+) -> Result<()> {
+    let resp = handle_remove_program(ctx, &name)
+        .instrument(command_span(ctx))
+        .await;
+    respond(ctx, resp).await
+}
+
+/// List the programs in this server
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_view",
+    on_error = "handle_error"
+)]
+async fn list(ctx: Context<'_>) -> Result<()> {
+    respond(
+        ctx,
+        handle_list_programs(ctx)
+            .instrument(command_span(ctx))
+            .await,
+    )
+    .await
+}
@@ -1,101 +1,118 @@
-use crate::config::{HISTORY_CHANNEL_NAME, NOTIFICATION_CHANNEL_NAME};
-use crate::discord_helpers::{find_channel, match_members};
-use crate::helpers::{checksum_matching, format_pairs, hash_seed, Pairing};
-use crate::types::Context;
-use crate::{helpers, ROLE_NAME};
+use crate::audit::set_audit_outcome;
+use crate::availability::suggest_times;
+use crate::discord_helpers::{match_members, Candidates, MemberMatching};
+use crate::guild_api::{GuildApi, SerenityGuild};
+use crate::helpers;
+use crate::helpers::{
+    checksum_matching, command_span, format_pairs, hash_seed, parse_key, validate_seed, Pairing,
+};
+use crate::history::format_round;
+use crate::matching::derive_seed;
+use crate::permissions::require_send;
+use crate::profile::format_profile;
+use crate::program::{autocomplete_program, Program, DEFAULT_PROGRAM};
+use crate::types::{Context, Data};
 use anyhow::{bail, ensure, Context as _, Error, Result};
 use helpers::handle_error;
+use itertools::Itertools;
 use poise::futures_util::future::try_join_all;
-use serenity::all::EditMessage;
+use serenity::all::{Timestamp, UserId};
+use tracing::{info, warn, Instrument, Span};
 
 /// Run the /send_pairing command
-async fn handle_send_pairing(ctx: Context<'_>, key: String) -> Result<String> {
-    println!("{} used /send_pairing", ctx.author());
-
-    let guild = ctx
-        .guild()
-        .context("This command must be called from a guild (server).")?
-        .clone();
-    let Some(role) = guild.role_by_name(ROLE_NAME) else {
-        bail!("Could not find a role with name `{ROLE_NAME}`");
-    };
-    let Some((seed_str, checksum)) = key.rsplit_once("_") else {
-        bail!("Invalid key. Please make sure you only use keys returned by /create_pairing.")
-    };
-    let Some(notification_channel) =
-        find_channel(&ctx, guild.id, NOTIFICATION_CHANNEL_NAME).await?
+pub async fn handle_send_pairing(
+    api: &impl GuildApi,
+    data: &Data,
+    program: &str,
+    key: String,
+) -> Result<String> {
+    Span::current().record("program", program);
+    let program = Program::load(api, data, program)?;
+    let role_id = program.role(api)?;
+    let Some((seed_str, candidate, checksum)) =
+        parse_key(&key).filter(|(seed_str, ..)| validate_seed(seed_str).is_ok())
     else {
-        bail!("Could not find notification channel");
-    };
-    let Some(history_channel) = find_channel(&ctx, guild.id, HISTORY_CHANNEL_NAME).await? else {
-        bail!("Could not find history channel");
+        bail!("Invalid key. Please make sure you only use keys returned by /create_pairing.")
     };
+    Span::current().record("seed", seed_str);
+    let notification_channel = program.notification_channel(api).await?;
+    let history_channel = program.history_channel(api).await?;
 
-    let seed = hash_seed(&seed_str);
+    let seed = hash_seed(seed_str);
 
-    let Pairing(pairs, _) = match_members(ctx, seed).await?;
+    let MemberMatching {
+        pairing: Pairing(pairs, ..),
+        week,
+        ..
+    } = match_members(
+        api,
+        data,
+        &program,
+        seed_str,
+        Candidates::Exactly(candidate),
+    )
+    .await?;
     let pairs_str = format_pairs(&pairs);
     ensure!(
-        checksum_matching(seed, &pairs) == checksum,
-        "Key mismatch. This can happen if you typed the key incorrectly, or the members with the \
-        matchy meetups role have changed since this key was generated. Please call /create_pairing \
-        again to get a new key."
+        checksum_matching(derive_seed(seed, candidate), &pairs) == checksum,
+        "Key mismatch. This can happen if you typed the key incorrectly or chose a different \
+        program, or the members of the program have changed since this key was generated. Please \
+        call /create_pairing again to get a new key."
     );
 
-    let notification_message = notification_channel
-        .say(
-            &ctx,
-            format!(
-                "Hey <@&{}>, here are the pairings for the next round of matchy meetups!\n\n{}",
-                role.id, pairs_str
-            ),
-        )
-        .await?;
-    let mut history_message = history_channel.say(&ctx, ".").await?;
-    history_message
-        .edit(
-            &ctx,
-            EditMessage::new().content(format!("{}\n{}", notification_message.link(), pairs_str)),
+    let notification_message = api
+        .post(
+            notification_channel,
+            program.notification(role_id, &pairs_str),
         )
         .await?;
+    for part in format_round(&key, Timestamp::now(), &notification_message.link, &pairs) {
+        api.post(history_channel, part).await?;
+    }
+    data.metrics.rounds_sent.inc();
+    data.metrics
+        .round_participants
+        .observe(pairs.iter().map(Vec::len).sum::<usize>() as f64);
 
+    let profiles = data
+        .store
+        .read(api.guild_id(), |data| data.profiles.clone());
     let mut messages_sent = 0;
 
     for pair in pairs {
         for user in &pair {
             let pairing: Vec<_> = pair.iter().filter(|u| *u != user).collect();
-            let pairing_str = try_join_all(pairing.iter().map(|uid| {
-                return async {
-                    let u = uid.to_user(&ctx).await?;
-                    Ok::<String, Error>(format!(
-                        "<@{}> ({})",
-                        u.id,
-                        u.global_name.unwrap_or(u.name)
-                    ))
-                };
+            let partners = try_join_all(pairing.iter().map(|uid| async {
+                Ok::<(UserId, String), Error>((**uid, api.user_name(**uid).await?))
             }))
             .await
-            .context("Unable to fetch names for user ids")?
-            .join(" and ");
-
-            let message_str = format!("Hey, thanks for joining ICSSC's Matchy Meetups. Your pairing \
-                 for this round is here! Please take this opportunity to reach out to them and \
-                 schedule some time to hang out in the next two weeks. \
-                 Don't forget to send pics to https://discord.com/channels/760915616793755669/1199228930222194779 \
-                 while you're there, and I hope you enjoy!\n\
-                 \t\t\t\t\t\t\t \\- Jeffrey \n\n\n\
-                 **Your pairing is with:** {pairing_str}\n\n\
-                 _(responses here will not be seen; please message Jeffrey directly if you have any questions)_");
-            let _ = user
-                .create_dm_channel(&ctx)
-                .await?
-                .say(&ctx, message_str)
-                .await;
-
+            .context("Unable to fetch names for user ids")?;
+            let pairing_str = partners
+                .iter()
+                .map(|(id, name)| format!("<@{id}> ({name})"))
+                .join(" and ");
+            let intros_str: String = partners
+                .iter()
+                .flat_map(|(id, name)| {
+                    let profile = profiles.get(id).and_then(format_profile)?;
+                    Some(format!("\n\n**About {name}:**\n{profile}"))
+                })
+                .collect();
+            let times_str = suggest_times(&profiles, week, *user, &pair)
+                .map(|times| format!("\n\n**You're all usually free:** {times}"))
+                .unwrap_or_default();
+            let message_str =
+                program.direct_message(&pairing_str, &format!("{intros_str}{times_str}"));
+            let sent = api.dm(*user, message_str).await;
+            data.metrics.record_direct_message(sent.is_ok());
+            if let Err(e) = sent {
+                warn!(user = %user, "Could not message user: {e:#}");
+                continue;
+            }
             messages_sent += 1;
         }
     }
-    println!("Messaged {messages_sent} users.");
+    info!("Messaged {messages_sent} users.");
     Ok(format!("Successfully messaged {messages_sent} users."))
 }
 
@@ -104,18 +121,31 @@ async fn handle_send_pairing(ctx: Context<'_>, key: String) -> Result<String> {
     slash_command,
     track_edits,
     hide_in_help,
-    required_permissions = "ADMINISTRATOR",
+    check = "require_send",
     on_error = "handle_error"
 )]
 pub async fn send_pairing(
     ctx: Context<'_>,
     #[description = "A pairing key returned by /create_pairing."] key: String,
+    #[description = "The program the pairing is for (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
 ) -> Result<(), Error> {
     ctx.defer().await?;
-    let resp = handle_send_pairing(ctx, key)
-        .await
-        .unwrap_or_else(|e| format!("Error: {}", e));
-    println!("{resp}");
+    let span = command_span(ctx);
+    let program = program.unwrap_or_else(|| DEFAULT_PROGRAM.to_owned());
+    let resp = async {
+        let api = SerenityGuild::new(ctx)?;
+        let resp = handle_send_pairing(&api, ctx.data(), &program, key)
+            .await
+            .unwrap_or_else(|e| format!("Error: {}", e));
+        set_audit_outcome(ctx, resp.clone()).await;
+        Ok::<_, Error>(resp)
+    }
+    .instrument(span.clone())
+    .await
+    .unwrap_or_else(|e| format!("Error: {}", e));
+    span.in_scope(|| info!("{resp}"));
     ctx.say(resp).await?;
     Ok(())
 }
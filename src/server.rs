@@ -0,0 +1,71 @@
+use crate::health::Health;
+use crate::metrics::Metrics;
+use anyhow::{Context as _, Result};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::Router;
+use serenity::all::Http;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+/// How often the discord REST API is checked for `/readyz`
+const REST_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a check waits for the discord REST API before reporting it as unreachable
+const REST_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the HTTP endpoints report on.
+#[derive(Clone)]
+pub struct ServerState {
+    pub metrics: Arc<Metrics>,
+    pub health: Arc<Health>,
+}
+
+async fn metrics(State(state): State<ServerState>) -> String {
+    state.metrics.render()
+}
+
+/// The process is alive whenever it can respond.
+async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// The bot is ready when it is connected to the gateway, has registered its commands and could
+/// reach the REST API at the last check.
+pub async fn readyz(State(state): State<ServerState>) -> (StatusCode, String) {
+    let problems = state.health.problems();
+    if problems.is_empty() {
+        (StatusCode::OK, "ready".to_owned())
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, problems.join("\n"))
+    }
+}
+
+/// Checks whether the discord REST API is reachable every REST_CHECK_INTERVAL, until the process
+/// exits. The result is recorded for `/readyz`, so probes don't each make a request.
+pub async fn check_rest_api(http: Arc<Http>, health: Arc<Health>) -> Result<()> {
+    let mut interval = tokio::time::interval(REST_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let check = tokio::time::timeout(REST_CHECK_TIMEOUT, http.get_current_user()).await;
+        health.set_rest_reachable(matches!(check, Ok(Ok(_))));
+    }
+}
+
+/// Serves `/metrics`, `/healthz` and `/readyz` on the port until the process exits.
+pub async fn serve(port: u16, state: ServerState) -> Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(self::metrics))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(state);
+    let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)))
+        .await
+        .with_context(|| format!("Unable to listen on port {port}"))?;
+    axum::serve(listener, app)
+        .await
+        .context("The HTTP server stopped")
+}
@@ -0,0 +1,457 @@
+use crate::guild_api::{GuildApi, SerenityGuild};
+use crate::helpers::{command_span, handle_error, respond};
+use crate::permissions::{require_configure, require_view};
+use crate::program::{autocomplete_program, DEFAULT_PROGRAM};
+use crate::storage::{Lookback, MentorshipSettings, PermissionLevel};
+use crate::types::Context;
+use anyhow::{ensure, Context as _, Result};
+use itertools::Itertools;
+use serenity::all::{ChannelId, GuildChannel, Role};
+use tracing::Instrument;
+
+/// The unit of a history lookback.
+#[derive(Clone, Copy, poise::ChoiceParameter)]
+pub(crate) enum LookbackUnit {
+    #[name = "days"]
+    Days,
+    #[name = "rounds"]
+    Rounds,
+}
+
+impl LookbackUnit {
+    /// Returns the lookback of `amount` of this unit.
+    pub(crate) fn lookback(self, amount: u32) -> Result<Lookback> {
+        ensure!(amount > 0, "The lookback must be at least 1.");
+        Ok(match self {
+            LookbackUnit::Days => Lookback::Days(amount),
+            LookbackUnit::Rounds => Lookback::Rounds(amount),
+        })
+    }
+}
+
+pub(crate) fn format_lookback(lookback: Lookback) -> String {
+    match lookback {
+        Lookback::Days(days) => format!("{days} days"),
+        Lookback::Rounds(rounds) => format!("{rounds} rounds"),
+    }
+}
+
+async fn handle_min_member_days(ctx: Context<'_>, program: &str, days: u32) -> Result<String> {
+    let guild_id = ctx
+        .guild_id()
+        .context("This command must be called from a guild (server).")?;
+    ctx.data().store.update_program(guild_id, program, |data| {
+        data.settings.min_member_days = days;
+    })?;
+    Ok(if days == 0 {
+        "Members will be matched as soon as they join.".to_owned()
+    } else {
+        format!("Members who joined less than {days} days ago will not be matched.")
+    })
+}
+
+async fn handle_history_lookback(
+    ctx: Context<'_>,
+    program: &str,
+    amount: u32,
+    unit: LookbackUnit,
+) -> Result<String> {
+    let guild_id = ctx
+        .guild_id()
+        .context("This command must be called from a guild (server).")?;
+    let lookback = unit.lookback(amount)?;
+    ctx.data().store.update_program(guild_id, program, |data| {
+        data.settings.history_lookback = lookback;
+    })?;
+    Ok(format!(
+        "Matching will avoid repeating pairs from the last {}.",
+        format_lookback(lookback)
+    ))
+}
+
+async fn handle_audit_channel(ctx: Context<'_>, channel: Option<ChannelId>) -> Result<String> {
+    let api = SerenityGuild::new(ctx)?;
+    // the change is recorded in the old channel, so turning off the audit log is itself recorded
+    ctx.data().store.update(api.guild_id(), |data| {
+        data.settings.audit_channel = channel;
+    })?;
+    Ok(match channel {
+        Some(channel) => format!("Admin actions will be recorded in <#{channel}>."),
+        None => "Admin actions will not be recorded.".to_owned(),
+    })
+}
+
+async fn handle_organizer_role(
+    ctx: Context<'_>,
+    role: Role,
+    level: Option<PermissionLevel>,
+) -> Result<String> {
+    let api = SerenityGuild::new(ctx)?;
+    ctx.data().store.update(api.guild_id(), |data| {
+        let roles = &mut data.settings.organizer_roles;
+        roles.retain(|(r, _)| *r != role.id);
+        if let Some(level) = level {
+            roles.push((role.id, level));
+        }
+    })?;
+    Ok(match level {
+        Some(level) => format!(
+            "Members with <@&{}> now have the `{level}` permission.",
+            role.id
+        ),
+        None => format!("<@&{}> is no longer a matchy organizer role.", role.id),
+    })
+}
+
+/// Which template /matchy_settings changes.
+#[derive(Clone, Copy)]
+enum TemplateKind {
+    Notification,
+    DirectMessage,
+}
+
+async fn handle_cadence(ctx: Context<'_>, program: &str, days: u32) -> Result<String> {
+    let guild_id = ctx
+        .guild_id()
+        .context("This command must be called from a guild (server).")?;
+    ensure!(days > 0, "The cadence must be at least 1 day.");
+    ctx.data().store.update_program(guild_id, program, |data| {
+        data.settings.cadence_days = days;
+    })?;
+    Ok(format!(
+        "`{program}` runs every {days} days. /create_pairing will warn about rounds created \
+        sooner than that."
+    ))
+}
+
+async fn handle_template(
+    ctx: Context<'_>,
+    program: &str,
+    kind: TemplateKind,
+    template: Option<String>,
+) -> Result<String> {
+    let guild_id = ctx
+        .guild_id()
+        .context("This command must be called from a guild (server).")?;
+    // slash command arguments can't contain line breaks, so allow writing them as \n
+    let template = template.map(|t| t.replace("\\n", "\n"));
+    let required = match kind {
+        TemplateKind::Notification => "{pairs}",
+        TemplateKind::DirectMessage => "{partners}",
+    };
+    if let Some(template) = &template {
+        ensure!(
+            template.contains(required),
+            "The template must contain `{required}`."
+        );
+    }
+    ctx.data()
+        .store
+        .update_program(guild_id, program, |data| match kind {
+            TemplateKind::Notification => data.settings.notification_template = template.clone(),
+            TemplateKind::DirectMessage => data.settings.dm_template = template.clone(),
+        })?;
+    Ok(match template {
+        Some(_) => format!("Updated the template for `{program}`."),
+        None => format!("`{program}` now uses the default template."),
+    })
+}
+
+async fn handle_mentorship(
+    ctx: Context<'_>,
+    program: &str,
+    mentor_role: Option<Role>,
+    mentees_per_mentor: u32,
+) -> Result<String> {
+    let guild_id = ctx
+        .guild_id()
+        .context("This command must be called from a guild (server).")?;
+    ensure!(
+        mentees_per_mentor > 0,
+        "Each mentor must be able to take at least 1 mentee."
+    );
+    let mentorship = mentor_role.as_ref().map(|role| MentorshipSettings {
+        mentor_role: role.id,
+        mentees_per_mentor,
+    });
+    ctx.data().store.update_program(guild_id, program, |data| {
+        data.settings.mentorship = mentorship;
+    })?;
+    Ok(match mentor_role {
+        Some(role) => format!(
+            "`{program}` will match each mentor with <@&{}> with between 1 and \
+            {mentees_per_mentor} of its members.",
+            role.id
+        ),
+        None => format!("`{program}` will match all of its members with each other."),
+    })
+}
+
+async fn handle_show_settings(ctx: Context<'_>, program: &str) -> Result<String> {
+    let guild_id = ctx
+        .guild_id()
+        .context("This command must be called from a guild (server).")?;
+    let program_settings = ctx.data().store.read_program(guild_id, program, |data| {
+        let template = |template: &Option<String>| match template {
+            Some(template) => format!("\n>>> {template}\n"),
+            None => "default\n".to_owned(),
+        };
+        format!(
+            "**Program:** `{program}`\n**Minimum days since joining:** {}\n\
+            **History lookback:** {}\n**Cadence:** every {} days\n**Mentorship:** {}\n\
+            **Announcement template:** {}**Message template:** {}",
+            data.settings.min_member_days,
+            format_lookback(data.settings.history_lookback),
+            data.settings.cadence_days,
+            data.settings
+                .mentorship
+                .map_or("off".to_owned(), |m| format!(
+                    "mentors with <@&{}>, up to {} mentees each",
+                    m.mentor_role, m.mentees_per_mentor
+                )),
+            template(&data.settings.notification_template),
+            template(&data.settings.dm_template),
+        )
+    })?;
+    let guild_settings = ctx.data().store.read(guild_id, |data| {
+        format!(
+            "**Audit channel:** {}\n**Organizer roles:** {}",
+            data.settings
+                .audit_channel
+                .map_or("none".to_owned(), |c| format!("<#{c}>")),
+            if data.settings.organizer_roles.is_empty() {
+                "none (only administrators can use matchy meetups commands)".to_owned()
+            } else {
+                data.settings
+                    .organizer_roles
+                    .iter()
+                    .map(|(role, level)| format!("<@&{role}> ({level})"))
+                    .join(", ")
+            }
+        )
+    });
+    Ok(format!("{guild_settings}\n\n{program_settings}"))
+}
+
+/// Change how matchy meetups works in this server
+#[poise::command(
+    slash_command,
+    hide_in_help,
+    ephemeral,
+    check = "require_view",
+    subcommands(
+        "min_member_days",
+        "history_lookback",
+        "cadence",
+        "notification_template",
+        "dm_template",
+        "mentorship",
+        "audit_channel",
+        "organizer_role",
+        "show"
+    ),
+    on_error = "handle_error"
+)]
+pub async fn matchy_settings(_ctx: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+/// Only match members who joined the server at least this many days ago
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_configure",
+    on_error = "handle_error"
+)]
+async fn min_member_days(
+    ctx: Context<'_>,
+    #[description = "The number of days (0 to match new members right away)."] days: u32,
+    #[description = "The program (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
+) -> Result<()> {
+    respond(
+        ctx,
+        handle_min_member_days(ctx, program.as_deref().unwrap_or(DEFAULT_PROGRAM), days)
+            .instrument(command_span(ctx))
+            .await,
+    )
+    .await
+}
+
+/// Set how much of the history channel is used to avoid repeated pairs
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_configure",
+    on_error = "handle_error"
+)]
+async fn history_lookback(
+    ctx: Context<'_>,
+    #[description = "How many days or rounds to look back."] amount: u32,
+    #[description = "Whether the amount is in days or rounds."] unit: LookbackUnit,
+    #[description = "The program (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
+) -> Result<()> {
+    respond(
+        ctx,
+        handle_history_lookback(
+            ctx,
+            program.as_deref().unwrap_or(DEFAULT_PROGRAM),
+            amount,
+            unit,
+        )
+        .instrument(command_span(ctx))
+        .await,
+    )
+    .await
+}
+
+/// Set how often a program runs
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_configure",
+    on_error = "handle_error"
+)]
+async fn cadence(
+    ctx: Context<'_>,
+    #[description = "The number of days between rounds."] days: u32,
+    #[description = "The program (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
+) -> Result<()> {
+    let program = program.as_deref().unwrap_or(DEFAULT_PROGRAM);
+    let resp = handle_cadence(ctx, program, days)
+        .instrument(command_span(ctx))
+        .await;
+    respond(ctx, resp).await
+}
+
+/// Set the announcement posted when a round is sent
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_configure",
+    on_error = "handle_error"
+)]
+async fn notification_template(
+    ctx: Context<'_>,
+    #[description = "The announcement, using {role} and {pairs} (leave empty for the default)."]
+    template: Option<String>,
+    #[description = "The program (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
+) -> Result<()> {
+    let program = program.as_deref().unwrap_or(DEFAULT_PROGRAM);
+    let resp = handle_template(ctx, program, TemplateKind::Notification, template)
+        .instrument(command_span(ctx))
+        .await;
+    respond(ctx, resp).await
+}
+
+/// Set the message sent to each member of a round
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_configure",
+    on_error = "handle_error"
+)]
+async fn dm_template(
+    ctx: Context<'_>,
+    #[description = "The message, using {partners} and {details} (leave empty for the default)."]
+    template: Option<String>,
+    #[description = "The program (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
+) -> Result<()> {
+    let program = program.as_deref().unwrap_or(DEFAULT_PROGRAM);
+    let resp = handle_template(ctx, program, TemplateKind::DirectMessage, template)
+        .instrument(command_span(ctx))
+        .await;
+    respond(ctx, resp).await
+}
+
+/// Match mentors with the program's members instead of matching everyone with each other
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_configure",
+    on_error = "handle_error"
+)]
+async fn mentorship(
+    ctx: Context<'_>,
+    #[description = "The role of the mentors (leave empty to match everyone with each other)."]
+    mentor_role: Option<Role>,
+    #[description = "The most mentees each mentor is matched with (default 1)."]
+    mentees_per_mentor: Option<u32>,
+    #[description = "The program (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
+) -> Result<()> {
+    let program = program.as_deref().unwrap_or(DEFAULT_PROGRAM);
+    let resp = handle_mentorship(ctx, program, mentor_role, mentees_per_mentor.unwrap_or(1))
+        .instrument(command_span(ctx))
+        .await;
+    respond(ctx, resp).await
+}
+
+/// Set the channel that admin actions are recorded in
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_configure",
+    on_error = "handle_error"
+)]
+async fn audit_channel(
+    ctx: Context<'_>,
+    #[description = "The channel to record admin actions in (leave empty to stop recording)."]
+    #[channel_types("Text")]
+    channel: Option<GuildChannel>,
+) -> Result<()> {
+    let resp = handle_audit_channel(ctx, channel.map(|c| c.id))
+        .instrument(command_span(ctx))
+        .await;
+    respond(ctx, resp).await
+}
+
+/// Let members with a role use matchy meetups commands without ADMINISTRATOR
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_configure",
+    on_error = "handle_error"
+)]
+async fn organizer_role(
+    ctx: Context<'_>,
+    #[description = "The organizer role."] role: Role,
+    #[description = "What the role allows: view drafts, create, send or configure (leave empty \
+    to remove the role)."]
+    level: Option<PermissionLevel>,
+) -> Result<()> {
+    let resp = handle_organizer_role(ctx, role, level)
+        .instrument(command_span(ctx))
+        .await;
+    respond(ctx, resp).await
+}
+
+/// Show the current settings
+#[poise::command(
+    slash_command,
+    ephemeral,
+    check = "require_view",
+    on_error = "handle_error"
+)]
+async fn show(
+    ctx: Context<'_>,
+    #[description = "The program (default matchy-meetups)."]
+    #[autocomplete = "autocomplete_program"]
+    program: Option<String>,
+) -> Result<()> {
+    let program = program.as_deref().unwrap_or(DEFAULT_PROGRAM);
+    let resp = handle_show_settings(ctx, program)
+        .instrument(command_span(ctx))
+        .await;
+    respond(ctx, resp).await
+}
@@ -0,0 +1,449 @@
+use crate::program::DEFAULT_PROGRAM;
+use anyhow::{anyhow, bail, ensure, Context as _, Result};
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, GuildId, RoleId, Timestamp, UserId};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{LazyLock, Mutex};
+
+/// How members' values for an attribute should affect who they are matched with.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, poise::ChoiceParameter)]
+pub enum AttributeMode {
+    /// Favor matching members with different values (for example, across project teams)
+    #[name = "mix"]
+    Mix,
+    /// Favor matching members with the same value (for example, the same major)
+    #[name = "same"]
+    Same,
+}
+
+/// What a matchy organizer role allows its members to do. Each level includes the ones before
+/// it, and members with ADMINISTRATOR can do everything.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    poise::ChoiceParameter,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum PermissionLevel {
+    /// See draft pairings, rules, attributes and settings
+    #[name = "view"]
+    View,
+    /// Create pairings that can be sent
+    #[name = "create"]
+    Create,
+    /// Send pairings
+    #[name = "send"]
+    Send,
+    /// Change rules, attributes and settings
+    #[name = "configure"]
+    Configure,
+}
+
+impl fmt::Display for PermissionLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PermissionLevel::View => "view",
+            PermissionLevel::Create => "create",
+            PermissionLevel::Send => "send",
+            PermissionLevel::Configure => "configure",
+        })
+    }
+}
+
+/// An attribute of members, such as their project team or class year. Each role is one possible
+/// value of the attribute.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attribute {
+    pub name: String,
+    pub mode: AttributeMode,
+    /// How strongly this attribute affects matching, relative to other attributes
+    pub weight: i64,
+    pub roles: Vec<RoleId>,
+}
+
+/// A weekly time window in a member's own time zone.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct WeeklyWindow {
+    /// Days since Monday
+    pub day: u8,
+    /// Minutes since midnight
+    pub start: u16,
+    /// Minutes since midnight (after `start`; may be 24:00)
+    pub end: u16,
+}
+
+/// A member's time zone and the times they are usually available each week.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    /// An IANA time zone name, such as America/Los_Angeles
+    pub timezone: String,
+    pub windows: Vec<WeeklyWindow>,
+}
+
+/// A member's answers to the profile questionnaire.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    /// Normalized (trimmed, lowercase) interests
+    pub interests: Vec<String>,
+    pub availability: String,
+    pub intro: String,
+    /// Structured availability, used to avoid matching members who are never free together
+    pub schedule: Option<Schedule>,
+}
+
+/// How far back the history channel is read when avoiding repeated matches.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Lookback {
+    /// Rounds sent within this many days
+    Days(u32),
+    /// This many of the most recent rounds
+    Rounds(u32),
+}
+
+impl Default for Lookback {
+    fn default() -> Self {
+        Lookback::Days(365)
+    }
+}
+
+/// Where the groups of an extra history source come from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistorySourceKind {
+    /// Rounds the bot recorded in another channel, such as an old history channel or the history
+    /// channel of another program
+    Channel(ChannelId),
+    /// Groups read from an uploaded file, which count as a single round from when it was uploaded
+    File {
+        uploaded: Timestamp,
+        groups: Vec<Vec<UserId>>,
+    },
+}
+
+/// Previous groups, on top of the ones in the program's history channel, that matching avoids
+/// repeating.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistorySource {
+    /// A name for the source, such as the name of the channel or file
+    pub name: String,
+    pub kind: HistorySourceKind,
+    /// How much of the source is used
+    pub lookback: Lookback,
+    /// How many times each group counts as a previous match (the history channel counts once)
+    pub weight: u32,
+}
+
+/// How a program matches mentors with mentees instead of matching everyone with each other.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct MentorshipSettings {
+    /// The role of the mentors. The members with the program's role are the mentees.
+    pub mentor_role: RoleId,
+    /// The most mentees each mentor is matched with
+    pub mentees_per_mentor: u32,
+}
+
+/// The number of days between rounds of a program, unless it is changed.
+pub const DEFAULT_CADENCE_DAYS: u32 = 14;
+
+/// Settings for one program that admins can change with /matchy_settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProgramSettings {
+    /// Members who joined the guild fewer than this many days ago are not matched yet
+    pub min_member_days: u32,
+    /// How much of the history channel is used to avoid repeats
+    pub history_lookback: Lookback,
+    /// How many days there should be between rounds
+    pub cadence_days: u32,
+    /// The announcement posted in the notification channel, or None for the default
+    pub notification_template: Option<String>,
+    /// The message sent to each member, or None for the default
+    pub dm_template: Option<String>,
+    /// Whether the program matches mentors with mentees, and how
+    pub mentorship: Option<MentorshipSettings>,
+}
+
+impl Default for ProgramSettings {
+    fn default() -> Self {
+        ProgramSettings {
+            min_member_days: 0,
+            history_lookback: Lookback::default(),
+            cadence_days: DEFAULT_CADENCE_DAYS,
+            notification_template: None,
+            dm_template: None,
+            mentorship: None,
+        }
+    }
+}
+
+/// A pool of members who are matched with each other, with its own channels, history, rules and
+/// settings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProgramData {
+    /// The role of the members in the program. The default program uses the role named
+    /// ROLE_NAME if this is not set.
+    pub role: Option<RoleId>,
+    /// Where rounds are announced. The default program uses the channel named
+    /// NOTIFICATION_CHANNEL_NAME if this is not set.
+    pub notification_channel: Option<ChannelId>,
+    /// Where rounds are recorded. The default program uses the channel named
+    /// HISTORY_CHANNEL_NAME if this is not set.
+    pub history_channel: Option<ChannelId>,
+    /// Pairs of members that must never be matched together.
+    pub never_match: Vec<(UserId, UserId)>,
+    /// Pairs of members that should be matched together whenever possible.
+    pub prefer_match: Vec<(UserId, UserId)>,
+    /// Attributes used as a soft objective when matching.
+    pub attributes: Vec<Attribute>,
+    /// Other previous groups that matching avoids repeating.
+    pub history_sources: Vec<HistorySource>,
+    pub settings: ProgramSettings,
+}
+
+/// The longest name a program can have, so that it fits in autocomplete choices.
+pub const MAX_PROGRAM_NAME_LENGTH: usize = 50;
+
+/// Settings for the whole guild that admins can change with /matchy_settings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// The channel admin actions are recorded in, if any
+    pub audit_channel: Option<ChannelId>,
+    /// Roles that allow members without ADMINISTRATOR to use matchy meetups commands
+    pub organizer_roles: Vec<(RoleId, PermissionLevel)>,
+}
+
+/// Per-guild settings and state that is not stored in discord itself.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(from = "StoredGuildData")]
+pub struct GuildData {
+    /// The programs other than the default one, and the default one once it has been changed.
+    pub programs: BTreeMap<String, ProgramData>,
+    /// Member profiles, used for matching by shared interests and introducing partners.
+    pub profiles: HashMap<UserId, Profile>,
+    /// Settings changed with /matchy_settings.
+    pub settings: Settings,
+}
+
+impl GuildData {
+    /// Returns the program with the name, if there is one. The default program always exists.
+    pub fn program(&self, name: &str) -> Option<&ProgramData> {
+        static DEFAULT: LazyLock<ProgramData> = LazyLock::new(ProgramData::default);
+        match self.programs.get(name) {
+            Some(program) => Some(program),
+            None if name == DEFAULT_PROGRAM => Some(&DEFAULT),
+            None => None,
+        }
+    }
+
+    /// Returns the program with the name for changing, if there is one.
+    pub fn program_mut(&mut self, name: &str) -> Option<&mut ProgramData> {
+        if name == DEFAULT_PROGRAM {
+            return Some(self.programs.entry(name.to_owned()).or_default());
+        }
+        self.programs.get_mut(name)
+    }
+
+    /// Adds a new program, or fails if there is already a program with the name or its history
+    /// channel is another program's. `named_history_channel` is the channel named
+    /// HISTORY_CHANNEL_NAME, which the default program uses if it has no history channel set.
+    pub fn create_program(
+        &mut self,
+        name: &str,
+        program: ProgramData,
+        named_history_channel: Option<ChannelId>,
+    ) -> Result<()> {
+        let name = name.trim();
+        ensure!(!name.is_empty(), "A program needs a name.");
+        ensure!(
+            name.chars().count() <= MAX_PROGRAM_NAME_LENGTH,
+            "Program names can be at most {MAX_PROGRAM_NAME_LENGTH} characters long."
+        );
+        ensure!(
+            self.program(name).is_none(),
+            "There is already a program named `{name}`."
+        );
+        // rounds from two programs in one channel would be read as each other's history
+        if let Some(channel) = program.history_channel {
+            let shared = self.program_names().into_iter().find(|other| {
+                let other_channel = match self.program(other).and_then(|p| p.history_channel) {
+                    None if other == DEFAULT_PROGRAM => named_history_channel,
+                    other_channel => other_channel,
+                };
+                other_channel == Some(channel)
+            });
+            if let Some(other) = shared {
+                bail!("<#{channel}> is already the history channel of `{other}`.");
+            }
+        }
+        self.programs.insert(name.to_owned(), program);
+        Ok(())
+    }
+
+    /// Removes a program and its rules, attributes and settings. The default program can't be
+    /// removed.
+    pub fn remove_program(&mut self, name: &str) -> Result<ProgramData> {
+        ensure!(
+            name != DEFAULT_PROGRAM,
+            "The `{DEFAULT_PROGRAM}` program can't be removed."
+        );
+        self.programs.remove(name).ok_or_else(|| no_program(name))
+    }
+
+    /// Returns the names of every program, starting with the default one.
+    pub fn program_names(&self) -> Vec<String> {
+        let others = self.programs.keys().filter(|n| *n != DEFAULT_PROGRAM);
+        std::iter::once(DEFAULT_PROGRAM.to_owned())
+            .chain(others.cloned())
+            .collect()
+    }
+}
+
+/// Guild data as it is stored, including the fields from before there were multiple programs,
+/// which are moved into the default program.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct StoredGuildData {
+    programs: BTreeMap<String, ProgramData>,
+    profiles: HashMap<UserId, Profile>,
+    settings: StoredSettings,
+    never_match: Vec<(UserId, UserId)>,
+    prefer_match: Vec<(UserId, UserId)>,
+    attributes: Vec<Attribute>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct StoredSettings {
+    #[serde(flatten)]
+    settings: Settings,
+    min_member_days: Option<u32>,
+    history_lookback: Option<Lookback>,
+}
+
+impl From<StoredGuildData> for GuildData {
+    fn from(stored: StoredGuildData) -> Self {
+        let mut data = GuildData {
+            programs: stored.programs,
+            profiles: stored.profiles,
+            settings: stored.settings.settings,
+        };
+        let has_legacy_data = !stored.never_match.is_empty()
+            || !stored.prefer_match.is_empty()
+            || !stored.attributes.is_empty()
+            || stored.settings.min_member_days.is_some()
+            || stored.settings.history_lookback.is_some();
+        if has_legacy_data && !data.programs.contains_key(DEFAULT_PROGRAM) {
+            let mut program = ProgramData {
+                never_match: stored.never_match,
+                prefer_match: stored.prefer_match,
+                attributes: stored.attributes,
+                ..ProgramData::default()
+            };
+            if let Some(days) = stored.settings.min_member_days {
+                program.settings.min_member_days = days;
+            }
+            if let Some(lookback) = stored.settings.history_lookback {
+                program.settings.history_lookback = lookback;
+            }
+            data.programs.insert(DEFAULT_PROGRAM.to_owned(), program);
+        }
+        data
+    }
+}
+
+fn no_program(name: &str) -> anyhow::Error {
+    anyhow!("There is no program named `{name}`. Use /matchy_programs list to see the programs.")
+}
+
+/// A simple JSON file store for GuildData. The whole file is rewritten on every update, which is
+/// fine for the small amount of data stored here.
+#[derive(Debug)]
+pub struct Store {
+    path: PathBuf,
+    guilds: Mutex<HashMap<GuildId, GuildData>>,
+}
+
+impl Store {
+    /// Loads the store from `path`, starting empty if the file does not exist yet.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let guilds = if path.exists() {
+            let contents = std::fs::read_to_string(&path)
+                .with_context(|| format!("Unable to read {}", path.display()))?;
+            serde_json::from_str(&contents)
+                .with_context(|| format!("Unable to parse {}", path.display()))?
+        } else {
+            HashMap::new()
+        };
+        Ok(Store {
+            path,
+            guilds: Mutex::new(guilds),
+        })
+    }
+
+    /// Runs `f` with the data for `guild_id` (or the default data if nothing is stored yet).
+    pub fn read<R>(&self, guild_id: GuildId, f: impl FnOnce(&GuildData) -> R) -> R {
+        let guilds = self
+            .guilds
+            .lock()
+            .expect("store lock should not be poisoned");
+        match guilds.get(&guild_id) {
+            Some(data) => f(data),
+            None => f(&GuildData::default()),
+        }
+    }
+
+    /// Runs `f` with the program named `program` in `guild_id`, or fails if there is no such
+    /// program.
+    pub fn read_program<R>(
+        &self,
+        guild_id: GuildId,
+        program: &str,
+        f: impl FnOnce(&ProgramData) -> R,
+    ) -> Result<R> {
+        self.read(guild_id, |data| match data.program(program) {
+            Some(program) => Ok(f(program)),
+            None => Err(no_program(program)),
+        })
+    }
+
+    /// Runs `f` with mutable access to the program named `program` in `guild_id`, then saves the
+    /// store to disk. Fails if there is no such program.
+    pub fn update_program<R>(
+        &self,
+        guild_id: GuildId,
+        program: &str,
+        f: impl FnOnce(&mut ProgramData) -> R,
+    ) -> Result<R> {
+        self.update(guild_id, |data| match data.program_mut(program) {
+            Some(program) => Ok(f(program)),
+            None => Err(no_program(program)),
+        })?
+    }
+
+    /// Runs `f` with mutable access to the data for `guild_id`, then saves the store to disk.
+    pub fn update<R>(&self, guild_id: GuildId, f: impl FnOnce(&mut GuildData) -> R) -> Result<R> {
+        let mut guilds = self
+            .guilds
+            .lock()
+            .expect("store lock should not be poisoned");
+        let result = f(guilds.entry(guild_id).or_default());
+        let contents = serde_json::to_string_pretty(&*guilds)?;
+        std::fs::write(&self.path, contents)
+            .with_context(|| format!("Unable to write {}", self.path.display()))?;
+        Ok(result)
+    }
+}
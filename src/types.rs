@@ -1,3 +1,32 @@
+use crate::health::Health;
+use crate::history::HistoryCache;
+use crate::member_index::MemberIndex;
+use crate::metrics::Metrics;
+use crate::storage::Store;
 use anyhow::Error;
-pub type Data = ();
+use std::sync::Arc;
+
+/// Data shared between all commands.
+#[derive(Debug)]
+pub struct Data {
+    pub store: Store,
+    /// The role to members index, if the GUILD_MEMBERS intent is enabled
+    pub member_index: Option<MemberIndex>,
+    pub history_cache: HistoryCache,
+    pub metrics: Arc<Metrics>,
+    pub health: Arc<Health>,
+}
+
+impl Data {
+    pub fn new(store: Store) -> Self {
+        Data {
+            store,
+            member_index: None,
+            history_cache: HistoryCache::default(),
+            metrics: Arc::default(),
+            health: Arc::default(),
+        }
+    }
+}
+
 pub type Context<'a> = poise::Context<'a, Data, Error>;
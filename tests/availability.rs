@@ -0,0 +1,174 @@
+//! Tests of parsing availability and comparing members' schedules.
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use matchy_meetups_bot::availability::{
+    availability_score, intersect, parse_windows, reference_week, suggest_times, utc_intervals,
+};
+use matchy_meetups_bot::storage::{Profile, Schedule};
+use serenity::all::{Timestamp, UserId};
+use std::collections::HashMap;
+
+#[test]
+fn windows_are_parsed() {
+    let windows = parse_windows("mon 18:00-21:00, weekends 10:00-24:00").unwrap();
+    let parsed: Vec<_> = windows.iter().map(|w| (w.day, w.start, w.end)).collect();
+    assert_eq!(
+        parsed,
+        [
+            (0, 18 * 60, 21 * 60),
+            (5, 10 * 60, 24 * 60),
+            (6, 10 * 60, 24 * 60)
+        ]
+    );
+    assert!(parse_windows("").unwrap().is_empty());
+}
+
+#[test]
+fn out_of_range_times_are_rejected() {
+    for windows in [
+        "mon 2000:00-21:00",
+        "mon 18:00-65535:00",
+        "mon 25:00-26:00",
+        "mon 18:00-24:01",
+        "mon 18:60-21:00",
+        "mon 21:00-18:00",
+    ] {
+        let err = parse_windows(windows).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("Could not understand `{windows}`. Use a format like `mon 18:00-21:00`.")
+        );
+    }
+}
+
+fn profile(timezone: &str, windows: &str) -> Profile {
+    Profile {
+        schedule: Some(Schedule {
+            timezone: timezone.to_owned(),
+            windows: parse_windows(windows).unwrap(),
+        }),
+        ..Profile::default()
+    }
+}
+
+fn week(date: &str) -> DateTime<Utc> {
+    reference_week(date, None)
+}
+
+fn minute(day: i64, hour: i64) -> i64 {
+    (day * 24 + hour) * 60
+}
+
+#[test]
+fn the_week_comes_from_the_seed_or_last_round() {
+    let monday = |date: &str| {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .unwrap()
+            .and_time(NaiveTime::MIN)
+            .and_utc()
+    };
+    let last_round = Timestamp::parse("2024-07-04T12:00:00Z").unwrap();
+    assert_eq!(
+        reference_week("2024-03-13 spring", Some(last_round)),
+        monday("2024-03-11")
+    );
+    assert_eq!(
+        reference_week("spring", Some(last_round)),
+        monday("2024-07-01")
+    );
+    assert_eq!(reference_week("spring", None), monday("2024-01-01"));
+}
+
+#[test]
+fn windows_use_the_offsets_of_the_reference_week() {
+    // New York moved to daylight saving time on Sunday 2024-03-10, and London on 2024-03-31
+    let new_york = profile("America/New_York", "mon 09:00-10:00, sun 12:00-13:00");
+    let schedule = new_york.schedule.as_ref().unwrap();
+    assert_eq!(
+        utc_intervals(schedule, week("2024-03-04")),
+        [
+            (minute(0, 14), minute(0, 15)),
+            (minute(6, 16), minute(6, 17))
+        ]
+    );
+    assert_eq!(
+        utc_intervals(schedule, week("2024-03-11")),
+        [
+            (minute(0, 13), minute(0, 14)),
+            (minute(6, 16), minute(6, 17))
+        ]
+    );
+
+    let london = profile("Europe/London", "mon 13:00-14:00");
+    let profiles = HashMap::from([(UserId::new(1), new_york), (UserId::new(2), london)]);
+    let group = [UserId::new(1), UserId::new(2)];
+    let score = availability_score(&profiles, week("2024-03-04"));
+    assert!(score(&group[0], &group[1]) < 0);
+    assert_eq!(
+        suggest_times(&profiles, week("2024-03-04"), group[0], &group),
+        None
+    );
+
+    let score = availability_score(&profiles, week("2024-03-11"));
+    assert_eq!(score(&group[0], &group[1]), 0);
+    assert_eq!(
+        suggest_times(&profiles, week("2024-03-11"), group[0], &group).unwrap(),
+        "Mon 09:00 – Mon 10:00 (America/New_York)"
+    );
+    assert_eq!(
+        suggest_times(&profiles, week("2024-03-11"), group[1], &group).unwrap(),
+        "Mon 13:00 – Mon 14:00 (Europe/London)"
+    );
+}
+
+#[test]
+fn windows_wrap_past_the_end_of_the_week() {
+    // Monday morning in Tokyo starts on Sunday in UTC
+    let tokyo = profile("Asia/Tokyo", "mon 00:00-10:00");
+    let london = profile("Europe/London", "sun 23:00-24:00, mon 00:00-00:30");
+    let tokyo_intervals = utc_intervals(tokyo.schedule.as_ref().unwrap(), week("2024-01-01"));
+    assert_eq!(
+        tokyo_intervals,
+        [(0, minute(0, 1)), (minute(6, 15), minute(7, 0))]
+    );
+    let london_intervals = utc_intervals(london.schedule.as_ref().unwrap(), week("2024-01-01"));
+    assert_eq!(
+        intersect(&tokyo_intervals, &london_intervals),
+        [(0, 30), (minute(6, 23), minute(7, 0))]
+    );
+
+    let profiles = HashMap::from([(UserId::new(1), tokyo), (UserId::new(2), london)]);
+    let group = [UserId::new(1), UserId::new(2)];
+    assert_eq!(
+        availability_score(&profiles, week("2024-01-01"))(&group[0], &group[1]),
+        0
+    );
+    assert_eq!(
+        suggest_times(&profiles, week("2024-01-01"), group[1], &group).unwrap(),
+        "Mon 00:00 – Mon 00:30, Sun 23:00 – Mon 00:00 (Europe/London)"
+    );
+}
+
+#[test]
+fn members_without_overlap_or_a_schedule() {
+    let profiles = HashMap::from([
+        (UserId::new(1), profile("UTC", "weekdays 09:00-17:00")),
+        (UserId::new(2), profile("UTC", "weekends 09:00-17:00")),
+        (UserId::new(3), Profile::default()),
+    ]);
+    let (a, b, c) = (UserId::new(1), UserId::new(2), UserId::new(3));
+    let week = week("2024-01-01");
+    assert!(intersect(
+        &utc_intervals(profiles[&a].schedule.as_ref().unwrap(), week),
+        &utc_intervals(profiles[&b].schedule.as_ref().unwrap(), week)
+    )
+    .is_empty());
+
+    let score = availability_score(&profiles, week);
+    assert!(score(&a, &b) < 0);
+    assert_eq!(score(&a, &c), 0);
+    assert_eq!(suggest_times(&profiles, week, a, &[a, b]), None);
+    // members without a schedule are left out, so there must be two others with one
+    assert_eq!(suggest_times(&profiles, week, c, &[a, c]), None);
+    assert_eq!(suggest_times(&profiles, week, c, &[a, b, c]), None);
+}
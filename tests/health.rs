@@ -0,0 +1,54 @@
+//! Tests of what the readiness endpoint reports.
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use matchy_meetups_bot::health::Health;
+use matchy_meetups_bot::metrics::Metrics;
+use matchy_meetups_bot::server::{readyz, ServerState};
+use std::sync::Arc;
+
+#[test]
+fn not_ready_until_started() {
+    let health = Health::default();
+    assert_eq!(
+        health.problems(),
+        vec![
+            "not connected to the discord gateway",
+            "commands are not registered",
+            "the discord REST API is not reachable"
+        ]
+    );
+
+    health.set_started();
+    health.set_rest_reachable(true);
+    assert!(health.problems().is_empty());
+
+    health.set_rest_reachable(false);
+    assert_eq!(
+        health.problems(),
+        vec!["the discord REST API is not reachable"]
+    );
+}
+
+#[tokio::test]
+async fn readyz_reports_the_cached_state() {
+    let health = Arc::new(Health::default());
+    let state = ServerState {
+        metrics: Arc::new(Metrics::default()),
+        health: health.clone(),
+    };
+
+    health.set_started();
+    let (status, body) = readyz(State(state.clone())).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body, "the discord REST API is not reachable");
+
+    health.set_rest_reachable(true);
+    let (status, body) = readyz(State(state.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "ready");
+
+    health.set_rest_reachable(false);
+    let (status, _) = readyz(State(state)).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+}
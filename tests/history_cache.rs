@@ -0,0 +1,312 @@
+//! Tests of reading previous rounds from the history channel.
+
+use matchy_meetups_bot::config::MAX_SEED_LENGTH;
+use matchy_meetups_bot::fake_guild::FakeGuild;
+use matchy_meetups_bot::guild_api::GuildApi;
+use matchy_meetups_bot::helpers::MESSAGE_LIMIT;
+use matchy_meetups_bot::history::{format_round, parse_history_file, HistoryAuthors, HistoryCache};
+use matchy_meetups_bot::storage::Lookback;
+use serenity::all::{ChannelId, Timestamp, UserId};
+
+const CHANNEL_NAME: &str = "history";
+const LINK: &str = "https://discord.com/channels/1/2/3";
+
+fn guild_with_channel() -> (FakeGuild, ChannelId) {
+    let mut guild = FakeGuild::new(1);
+    let channel = guild.add_channel(CHANNEL_NAME);
+    (guild, channel)
+}
+
+/// A history message in the format used before structured rounds.
+fn round(pairs: &[(u64, u64)]) -> String {
+    let lines: Vec<String> = pairs
+        .iter()
+        .map(|(a, b)| format!("<@{a}> and <@{b}>"))
+        .collect();
+    format!("{LINK}\n{}", lines.join("\n"))
+}
+
+/// The history messages for a structured round.
+fn structured_round(id: &str, pairs: &[(u64, u64)]) -> Vec<String> {
+    let groups: Vec<Vec<UserId>> = pairs.iter().map(|(a, b)| ids(*a, *b)).collect();
+    format_round(id, Timestamp::now(), LINK, &groups)
+}
+
+async fn previous_pairs(
+    cache: &HistoryCache,
+    guild: &FakeGuild,
+    channel: ChannelId,
+    lookback: Lookback,
+) -> Vec<Vec<UserId>> {
+    previous_groups(cache, guild, channel, HistoryAuthors::Bot, lookback).await
+}
+
+async fn previous_groups(
+    cache: &HistoryCache,
+    guild: &FakeGuild,
+    channel: ChannelId,
+    authors: HistoryAuthors,
+    lookback: Lookback,
+) -> Vec<Vec<UserId>> {
+    cache
+        .previous_matches(guild, channel, authors, lookback)
+        .await
+        .unwrap()
+        .matches
+        .into_iter()
+        .map(|m| m.members)
+        .collect()
+}
+
+fn ids(a: u64, b: u64) -> Vec<UserId> {
+    vec![UserId::new(a), UserId::new(b)]
+}
+
+#[tokio::test]
+async fn rounds_behind_chatter_are_not_truncated() {
+    let (guild, channel) = guild_with_channel();
+    guild.add_message(channel, guild.bot_id, &round(&[(10, 11)]));
+    for i in 0..1500 {
+        guild.add_message(channel, UserId::new(5), &format!("chatter {i}"));
+    }
+    guild.add_message(channel, guild.bot_id, &round(&[(12, 13), (14, 15)]));
+
+    let cache = HistoryCache::default();
+    let pairs = previous_pairs(&cache, &guild, channel, Lookback::default()).await;
+    assert_eq!(pairs, vec![ids(12, 13), ids(14, 15), ids(10, 11)]);
+}
+
+#[tokio::test]
+async fn source_channels_read_groups_posted_by_members() {
+    let (guild, channel) = guild_with_channel();
+    guild.add_message(channel, guild.bot_id, &round(&[(10, 11)]));
+    guild.add_message(
+        channel,
+        UserId::new(5),
+        "Hackathon teams\nTeam 1: <@12> <@13> <@14>\nTeam 2: 123456789012345678, <@15>\nJudge: <@16>",
+    );
+    guild.add_message(channel, UserId::new(6), "thanks <@5>!");
+
+    let cache = HistoryCache::default();
+    let groups = previous_groups(
+        &cache,
+        &guild,
+        channel,
+        HistoryAuthors::Anyone,
+        Lookback::Rounds(2),
+    )
+    .await;
+    let members = |ids: &[u64]| ids.iter().copied().map(UserId::new).collect::<Vec<_>>();
+    assert_eq!(
+        groups,
+        vec![
+            members(&[12, 13, 14]),
+            members(&[123456789012345678, 15]),
+            members(&[10, 11]),
+        ]
+    );
+    // the same channel used as a program's history channel only trusts the bot
+    let pairs = previous_pairs(&cache, &guild, channel, Lookback::Rounds(2)).await;
+    assert_eq!(pairs, vec![ids(10, 11)]);
+}
+
+#[tokio::test]
+async fn lookback_in_rounds() {
+    let (guild, channel) = guild_with_channel();
+    guild.add_message(channel, guild.bot_id, &round(&[(10, 11)]));
+    guild.add_message(channel, guild.bot_id, &round(&[(12, 13)]));
+    guild.add_message(channel, UserId::new(5), "chatter is not a round");
+    guild.add_message(channel, guild.bot_id, &round(&[(14, 15)]));
+
+    let cache = HistoryCache::default();
+    let pairs = previous_pairs(&cache, &guild, channel, Lookback::Rounds(2)).await;
+    assert_eq!(pairs, vec![ids(14, 15), ids(12, 13)]);
+
+    // a longer lookback fetches the older rounds that were not needed before
+    let pairs = previous_pairs(&cache, &guild, channel, Lookback::Rounds(5)).await;
+    assert_eq!(pairs, vec![ids(14, 15), ids(12, 13), ids(10, 11)]);
+}
+
+#[tokio::test]
+async fn new_rounds_are_added_to_the_cache() {
+    let (guild, channel) = guild_with_channel();
+    let cache = HistoryCache::default();
+    assert!(previous_pairs(&cache, &guild, channel, Lookback::default())
+        .await
+        .is_empty());
+
+    guild.add_message(channel, guild.bot_id, &round(&[(10, 11)]));
+    for i in 0..250 {
+        guild.add_message(channel, UserId::new(5), &format!("chatter {i}"));
+    }
+    let pairs = previous_pairs(&cache, &guild, channel, Lookback::default()).await;
+    assert_eq!(pairs, vec![ids(10, 11)]);
+
+    guild.add_message(channel, guild.bot_id, &round(&[(12, 13)]));
+    let pairs = previous_pairs(&cache, &guild, channel, Lookback::default()).await;
+    assert_eq!(pairs, vec![ids(12, 13), ids(10, 11)]);
+}
+
+#[tokio::test]
+async fn deleted_rounds_are_removed_from_the_cache() {
+    let (guild, channel) = guild_with_channel();
+    guild.add_message(channel, guild.bot_id, &round(&[(10, 11)]));
+    guild.add_message(channel, guild.bot_id, &round(&[(12, 13)]));
+    let deleted = guild.add_message(channel, guild.bot_id, &round(&[(14, 15)]));
+    let cache = HistoryCache::default();
+    let pairs = previous_pairs(&cache, &guild, channel, Lookback::Rounds(2)).await;
+    assert_eq!(pairs, vec![ids(14, 15), ids(12, 13)]);
+
+    guild
+        .messages
+        .lock()
+        .unwrap()
+        .get_mut(&channel)
+        .unwrap()
+        .retain(|m| m.id != deleted);
+    cache.messages_deleted(channel, &[deleted]).await;
+    // the round before the deleted one is fetched to fill the lookback
+    let pairs = previous_pairs(&cache, &guild, channel, Lookback::Rounds(2)).await;
+    assert_eq!(pairs, vec![ids(12, 13), ids(10, 11)]);
+}
+
+#[tokio::test]
+async fn structured_rounds() {
+    let (guild, channel) = guild_with_channel();
+    guild.add_message(channel, guild.bot_id, &round(&[(10, 11)]));
+    for part in structured_round("2024-01-01_abc", &[(12, 13), (14, 15)]) {
+        guild.add_message(channel, guild.bot_id, &part);
+    }
+
+    let cache = HistoryCache::default();
+    let pairs = previous_pairs(&cache, &guild, channel, Lookback::default()).await;
+    assert_eq!(pairs, vec![ids(12, 13), ids(14, 15), ids(10, 11)]);
+}
+
+#[tokio::test]
+async fn large_rounds_are_split_into_parts() {
+    let (guild, channel) = guild_with_channel();
+    let pairs: Vec<(u64, u64)> = (0..300).map(|i| (1000 + 2 * i, 1001 + 2 * i)).collect();
+    let parts = structured_round("big", &pairs);
+    assert!(parts.len() > 1);
+    assert!(parts.iter().all(|p| p.len() <= 2000));
+    guild.add_message(channel, guild.bot_id, &round(&[(10, 11)]));
+    for part in parts {
+        guild.add_message(channel, guild.bot_id, &part);
+    }
+
+    // all of the parts count as one round
+    let cache = HistoryCache::default();
+    let previous = previous_pairs(&cache, &guild, channel, Lookback::Rounds(1)).await;
+    assert_eq!(previous.len(), 300);
+    let previous = previous_pairs(&cache, &guild, channel, Lookback::Rounds(2)).await;
+    assert_eq!(previous.len(), 301);
+}
+
+#[tokio::test]
+async fn comments_and_forgeries_are_ignored() {
+    let (guild, channel) = guild_with_channel();
+    let member = UserId::new(5);
+    guild.add_message(channel, member, "thanks <@10> and <@11>!");
+    guild.add_message(channel, guild.bot_id, "<@12> <@13>");
+    // only messages from the bot are trusted
+    guild.add_message(channel, member, &round(&[(12, 13)]));
+    // structured rounds are only trusted from the bot
+    for part in structured_round("forged", &[(14, 15)]) {
+        guild.add_message(channel, member, &part);
+    }
+    // and only if their checksum matches
+    for part in structured_round("tampered", &[(16, 17)]) {
+        guild.add_message(channel, guild.bot_id, &part.replace("17", "18"));
+    }
+
+    let cache = HistoryCache::default();
+    assert!(previous_pairs(&cache, &guild, channel, Lookback::default())
+        .await
+        .is_empty());
+}
+
+#[tokio::test]
+async fn edited_rounds_are_flagged() {
+    let (guild, channel) = guild_with_channel();
+    let placeholder = guild.add_message(channel, guild.bot_id, ".");
+    guild
+        .edit(channel, placeholder, round(&[(10, 11)]))
+        .await
+        .unwrap();
+    let tampered = guild.add_message(channel, guild.bot_id, &round(&[(12, 13)]));
+    {
+        let mut messages = guild.messages.lock().unwrap();
+        let message = messages
+            .get_mut(&channel)
+            .unwrap()
+            .iter_mut()
+            .find(|m| m.id == tampered)
+            .unwrap();
+        message.content = round(&[(12, 14)]);
+        message.edited_timestamp = Some(
+            Timestamp::from_unix_timestamp(message.timestamp.unix_timestamp() + 3600).unwrap(),
+        );
+    }
+
+    let cache = HistoryCache::default();
+    let history = cache
+        .previous_matches(&guild, channel, HistoryAuthors::Bot, Lookback::default())
+        .await
+        .unwrap();
+    // an edit right after posting is how rounds used to be posted, so it is trusted
+    let matches: Vec<_> = history
+        .matches
+        .iter()
+        .map(|m| (m.members.clone(), m.message_id))
+        .collect();
+    assert_eq!(matches, vec![(ids(10, 11), placeholder)]);
+    assert_eq!(history.edited_messages, vec![tampered]);
+}
+
+#[test]
+fn history_files_have_one_group_per_line() {
+    let contents = "Team,Members\n\
+        Team 1,<@123456789012345678>, <@!223456789012345678>\n\
+        \n\
+        Team 2: 323456789012345678 423456789012345678 523456789012345678\n";
+    let groups = parse_history_file(contents).unwrap();
+    assert_eq!(
+        groups,
+        vec![
+            ids(123456789012345678, 223456789012345678),
+            vec![
+                UserId::new(323456789012345678),
+                UserId::new(423456789012345678),
+                UserId::new(523456789012345678),
+            ],
+        ]
+    );
+
+    let err = parse_history_file("<@123456789012345678>\n").unwrap_err();
+    assert_eq!(err.to_string(), "Line 1 has only one member.");
+    let err = parse_history_file("no members here").unwrap_err();
+    assert_eq!(err.to_string(), "The file has no groups of members.");
+}
+
+#[test]
+fn rounds_with_the_longest_key_fit_in_messages() {
+    let key = format!("{}_0123abcd.50", "🎉".repeat(MAX_SEED_LENGTH));
+    let groups: Vec<Vec<UserId>> = (0..200u64)
+        .map(|i| {
+            ids(
+                100_000_000_000_000_000 + 2 * i,
+                100_000_000_000_000_001 + 2 * i,
+            )
+        })
+        .collect();
+    let messages = format_round(&key, Timestamp::now(), LINK, &groups);
+    assert!(messages.len() > 1);
+    for message in &messages {
+        assert!(
+            message.chars().count() <= MESSAGE_LIMIT,
+            "{}",
+            message.len()
+        );
+    }
+}
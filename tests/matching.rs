@@ -0,0 +1,166 @@
+//! Deterministic tests of the choices graph_pair() and best_graph_pair() make.
+
+use matchy_meetups_bot::attributes::attribute_score;
+use matchy_meetups_bot::config::MAX_ATTRIBUTE_WEIGHT;
+use matchy_meetups_bot::helpers::{Match, Pairing};
+use matchy_meetups_bot::matching::{best_graph_pair, graph_pair, rate_pairing, MatchingOptions};
+use matchy_meetups_bot::profile::interest_score;
+use matchy_meetups_bot::storage::{Attribute, AttributeMode, Profile};
+use serenity::all::{RoleId, UserId};
+use std::collections::HashMap;
+
+/// The member who was added to a pair to make a triple.
+fn remainder(pairing: &Pairing<u32>) -> u32 {
+    let Pairing(_, _, _, details) = pairing;
+    details
+        .iter()
+        .find_map(|d| d.remainder)
+        .expect("an odd number of members should have a remainder")
+}
+
+/// The groups of a pairing, with their members sorted, in order.
+fn sorted_groups<T: Ord + Copy>(pairing: &Pairing<T>) -> Vec<Match<T>> {
+    let Pairing(pairs, ..) = pairing;
+    let mut groups: Vec<Match<T>> = pairs
+        .iter()
+        .map(|p| {
+            let mut p = p.clone();
+            p.sort();
+            p
+        })
+        .collect();
+    groups.sort();
+    groups
+}
+
+#[test]
+fn other_history_does_not_count_as_triples() {
+    // 0, 1 and 2 were the program's last triple, so one of the others should be the remainder
+    let history: Vec<Match<u32>> = vec![vec![0, 1, 2]];
+    // a team of four from another source, with a high weight, is not a triple
+    let options = MatchingOptions {
+        other_history: std::iter::repeat_n(vec![3, 4, 5, 6], 5).collect(),
+        ..Default::default()
+    };
+    for seed in 0..20 {
+        let without = graph_pair(
+            (0..9).collect(),
+            &history,
+            &MatchingOptions::default(),
+            seed,
+        )
+        .unwrap();
+        let with = graph_pair((0..9).collect(), &history, &options, seed).unwrap();
+        assert!(remainder(&without) >= 3);
+        assert_eq!(remainder(&with), remainder(&without), "seed {seed}");
+    }
+}
+
+#[test]
+fn a_shared_attribute_decides_the_best_candidate() {
+    let members: Vec<(UserId, Vec<RoleId>)> = [(1, 10), (2, 10), (3, 11), (4, 11)]
+        .into_iter()
+        .map(|(id, role)| (UserId::new(id), vec![RoleId::new(role)]))
+        .collect();
+    let ids: Vec<UserId> = members.iter().map(|(id, _)| *id).collect();
+    let same_team = |weight| Attribute {
+        name: "team".to_owned(),
+        mode: AttributeMode::Same,
+        weight,
+        roles: vec![RoleId::new(10), RoleId::new(11)],
+    };
+    let options = MatchingOptions {
+        pair_scores: vec![attribute_score(vec![same_team(1)], &members)],
+        ..Default::default()
+    };
+    let teams = vec![vec![ids[0], ids[1]], vec![ids[2], ids[3]]];
+
+    let mut differs = false;
+    for seed in 0..20 {
+        let (with, ..) = best_graph_pair(ids.clone(), &[], &options, seed, 10).unwrap();
+        assert_eq!(sorted_groups(&with), teams, "seed {seed}");
+        let (without, ..) =
+            best_graph_pair(ids.clone(), &[], &MatchingOptions::default(), seed, 10).unwrap();
+        differs |= sorted_groups(&without) != teams;
+    }
+    assert!(
+        differs,
+        "without the attribute, some seeds should pair across teams"
+    );
+
+    // weights saved before the cap can't outweigh repeats
+    let score = attribute_score(vec![same_team(i64::MAX)], &members);
+    assert_eq!(score(&ids[0], &ids[1]), MAX_ATTRIBUTE_WEIGHT);
+}
+
+#[test]
+fn shared_interests_raise_the_rating() {
+    let ids: Vec<UserId> = (1..=4).map(UserId::new).collect();
+    let profiles: HashMap<UserId, Profile> = [(1, "chess"), (2, "chess"), (3, "go"), (4, "go")]
+        .into_iter()
+        .map(|(id, interest)| {
+            let profile = Profile {
+                interests: vec![interest.to_owned()],
+                ..Profile::default()
+            };
+            (UserId::new(id), profile)
+        })
+        .collect();
+    let options = MatchingOptions {
+        pair_scores: vec![interest_score(&profiles)],
+        ..Default::default()
+    };
+    let shared = vec![vec![ids[0], ids[1]], vec![ids[2], ids[3]]];
+
+    for seed in 0..20 {
+        let (with, _, rating) = best_graph_pair(ids.clone(), &[], &options, seed, 10).unwrap();
+        assert_eq!(sorted_groups(&with), shared, "seed {seed}");
+        assert!(rating > 0, "seed {seed}");
+        assert_eq!(rating, rate_pairing(&with, &[]));
+        let (_, _, rating) =
+            best_graph_pair(ids.clone(), &[], &MatchingOptions::default(), seed, 10).unwrap();
+        assert_eq!(rating, 0, "seed {seed}");
+    }
+}
+
+#[test]
+fn the_remainder_rotates_away_from_recent_triples() {
+    // everyone but 6 was in a triple last time, and 0 to 5 can all be paired with someone new
+    let history: Vec<Match<u32>> = vec![vec![0, 1, 2], vec![3, 4, 5]];
+    for seed in 0..20 {
+        let pairing = graph_pair(
+            (0..7).collect(),
+            &history,
+            &MatchingOptions::default(),
+            seed,
+        );
+        assert_eq!(remainder(&pairing.unwrap()), 6, "seed {seed}");
+    }
+
+    // over several rounds, the remainder is someone who hasn't been in a triple yet
+    for seed in 0..20 {
+        let mut history: Vec<Match<u32>> = Vec::new();
+        let mut in_triples: Vec<u32> = Vec::new();
+        for round in 0..3 {
+            let pairing = graph_pair(
+                (0..9).collect(),
+                &history,
+                &MatchingOptions::default(),
+                seed + round,
+            )
+            .unwrap();
+            let remainder = remainder(&pairing);
+            assert!(
+                !in_triples.contains(&remainder),
+                "seed {seed}, round {round}: {remainder} was already in a triple"
+            );
+            let Pairing(pairs, ..) = pairing;
+            for group in &pairs {
+                if group.len() > 2 {
+                    in_triples.extend(group);
+                }
+            }
+            history.extend(pairs);
+        }
+    }
+}
@@ -0,0 +1,307 @@
+//! Property tests for the invariants of graph_pair() and best_graph_pair(), including in
+//! mentorship mode.
+
+use itertools::Itertools;
+use matchy_meetups_bot::helpers::{Match, Pairing};
+use matchy_meetups_bot::matching::{best_graph_pair, graph_pair, MatchingOptions, Mentorship};
+use proptest::prelude::*;
+use proptest::sample::subsequence;
+use std::collections::HashSet;
+
+/// A list of participants along with some previous groups of (not necessarily current)
+/// participants.
+fn participants_and_history() -> impl Strategy<Value = (Vec<u32>, Vec<Match<u32>>)> {
+    (2..80u32).prop_flat_map(|n| {
+        // include some ids that are no longer participating in the history
+        let ids: Vec<u32> = (0..n + 5).collect();
+        let group = subsequence(ids, 2..=3);
+        (Just((0..n).collect()), prop::collection::vec(group, 0..60))
+    })
+}
+
+/// Pairs of ids that have been in the same group before.
+fn history_edges(history: &[Match<u32>]) -> HashSet<(u32, u32)> {
+    history
+        .iter()
+        .flat_map(|m| m.iter().tuple_combinations())
+        .map(|(&a, &b)| (a.min(b), a.max(b)))
+        .collect()
+}
+
+/// Checks the invariants that hold for every grouping of `participants`, whatever the group
+/// sizes: everyone is in exactly one group, and repeats are counted and reported.
+fn check_groups(
+    pairing: &Pairing<u32>,
+    participants: &[u32],
+    history: &[Match<u32>],
+) -> Result<(), TestCaseError> {
+    let Pairing(groups, imperfect_matches, repeated_pairs, details) = pairing;
+
+    // every participant appears in exactly one group
+    let mut members: Vec<u32> = groups.iter().flatten().cloned().collect();
+    members.sort();
+    prop_assert_eq!(&members, participants);
+
+    // an edge from the history is only used between imperfect matches
+    let previous = history_edges(history);
+    let mut repeats = 0;
+    for (&a, &b) in groups.iter().flat_map(|g| g.iter().tuple_combinations()) {
+        if previous.contains(&(a.min(b), a.max(b))) {
+            repeats += 1;
+            prop_assert!(
+                imperfect_matches.contains(&a) && imperfect_matches.contains(&b),
+                "{} and {} met before but are not listed as imperfect matches",
+                a,
+                b
+            );
+        }
+    }
+    prop_assert_eq!(repeats, *repeated_pairs);
+    prop_assert_eq!(details.len(), groups.len());
+    Ok(())
+}
+
+/// Checks the invariants that hold for every pairing of `participants` outside mentorship mode.
+fn check_pairing(
+    pairing: &Pairing<u32>,
+    participants: &[u32],
+    history: &[Match<u32>],
+) -> Result<(), TestCaseError> {
+    check_groups(pairing, participants, history)?;
+
+    // groups are pairs, except for at most one triple
+    let Pairing(pairs, ..) = pairing;
+    prop_assert!(pairs.iter().all(|m| m.len() == 2 || m.len() == 3));
+    prop_assert!(pairs.iter().filter(|m| m.len() == 3).count() <= 1);
+    Ok(())
+}
+
+/// A number of mentors and a mentee capacity that leave every mentor with between one and that
+/// many mentees, for `participants` participants.
+fn mentors_and_capacity(participants: u32) -> impl Strategy<Value = (u32, usize)> {
+    (1..=participants / 2).prop_flat_map(move |mentors| {
+        let min_capacity = (participants - mentors).div_ceil(mentors) as usize;
+        (Just(mentors), min_capacity..min_capacity + 3)
+    })
+}
+
+#[test]
+fn everyone_who_met_before_in_a_triple_is_an_imperfect_match() {
+    // 0 and 1 have met, so whichever of them isn't paired with 2 is the remainder, and both must
+    // be listed (not only the remainder)
+    let history = vec![vec![0, 1]];
+    for seed in 0..20 {
+        let Pairing(pairs, mut imperfect_matches, repeated_pairs, _) =
+            graph_pair(vec![0, 1, 2], &history, &MatchingOptions::default(), seed).unwrap();
+        imperfect_matches.sort();
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(imperfect_matches, [0, 1]);
+        assert_eq!(repeated_pairs, 1);
+    }
+}
+
+#[test]
+fn repeats_are_reduced_when_no_single_swap_helps() {
+    let met = [
+        ((0, 2), 3),
+        ((0, 3), 1),
+        ((0, 4), 1),
+        ((0, 5), 2),
+        ((1, 2), 3),
+        ((1, 3), 3),
+        ((1, 4), 2),
+        ((2, 3), 3),
+        ((2, 4), 1),
+        ((2, 5), 3),
+        ((3, 4), 1),
+        ((3, 5), 3),
+    ];
+    let history: Vec<Match<u32>> = met
+        .iter()
+        .flat_map(|&((a, b), times)| itertools::repeat_n(vec![a, b], times))
+        .collect();
+    let times_met = |pairs: &[Match<u32>]| -> usize {
+        pairs
+            .iter()
+            .map(|p| {
+                history
+                    .iter()
+                    .filter(|m| m.iter().sorted().eq(p.iter().sorted()))
+                    .count()
+            })
+            .sum()
+    };
+
+    // 2 and 3 have met 3 times, but swapping partners with either other pair doesn't help
+    let stuck = [vec![2, 3], vec![0, 1], vec![4, 5]];
+    assert_eq!(times_met(&stuck), 3);
+    for other in [&stuck[1], &stuck[2]] {
+        for swapped in [
+            [vec![2, other[0]], vec![3, other[1]]],
+            [vec![2, other[1]], vec![3, other[0]]],
+        ] {
+            assert!(times_met(&swapped) >= 3);
+        }
+    }
+    // but re-pairing all three pairs does
+    assert_eq!(times_met(&[vec![2, 4], vec![0, 3], vec![1, 5]]), 2);
+
+    for seed in 0..50 {
+        let Pairing(pairs, _, _, _) = graph_pair(
+            (0..6).collect(),
+            &history,
+            &MatchingOptions::default(),
+            seed,
+        )
+        .unwrap();
+        assert_eq!(times_met(&pairs), 2, "{pairs:?}");
+    }
+}
+
+proptest! {
+    #[test]
+    fn graph_pair_invariants((participants, history) in participants_and_history(), seed: u64) {
+        let pairing = graph_pair(
+            participants.clone(),
+            &history,
+            &MatchingOptions::default(),
+            seed,
+        )
+        .unwrap();
+        check_pairing(&pairing, &participants, &history)?;
+    }
+
+    #[test]
+    fn best_graph_pair_invariants(
+        (participants, history) in participants_and_history(),
+        seed: u64,
+        candidates in 1..5usize,
+    ) {
+        let (pairing, candidate, _) = best_graph_pair(
+            participants.clone(),
+            &history,
+            &MatchingOptions::default(),
+            seed,
+            candidates,
+        )
+        .unwrap();
+        prop_assert!(candidate < candidates);
+        check_pairing(&pairing, &participants, &history)?;
+    }
+
+    #[test]
+    fn never_match_is_respected(
+        ((participants, history), never) in participants_and_history()
+            .prop_filter("rules need enough members to be satisfiable", |(p, _)| p.len() >= 24)
+            .prop_flat_map(|(participants, history)| {
+                let pair = subsequence(participants.clone(), 2);
+                (Just((participants, history)), prop::collection::vec(pair, 0..10))
+            }),
+        seed: u64,
+    ) {
+        let options = MatchingOptions {
+            never_match: never.iter().map(|p| (p[0], p[1])).collect(),
+            ..Default::default()
+        };
+        // with at least 24 members and at most 10 rules, every member can be paired with more
+        // than half of the others, so a perfect matching exists, and the remainder has fewer
+        // rules than there are pairs to join
+        let pairing = graph_pair(participants.clone(), &history, &options, seed).unwrap();
+        check_pairing(&pairing, &participants, &history)?;
+        for m in &pairing.0 {
+            for pair in &never {
+                prop_assert!(!(m.contains(&pair[0]) && m.contains(&pair[1])));
+            }
+        }
+    }
+
+    #[test]
+    fn deterministic_per_seed(
+        (participants, history) in participants_and_history(),
+        seed: u64,
+        candidates in 1..5usize,
+    ) {
+        let options = MatchingOptions::default();
+        let Pairing(pairs, imperfect_matches, repeated_pairs, _) =
+            graph_pair(participants.clone(), &history, &options, seed).unwrap();
+        let Pairing(pairs_again, imperfect_matches_again, repeated_pairs_again, _) =
+            graph_pair(participants.clone(), &history, &options, seed).unwrap();
+        prop_assert_eq!(pairs, pairs_again);
+        prop_assert_eq!(imperfect_matches, imperfect_matches_again);
+        prop_assert_eq!(repeated_pairs, repeated_pairs_again);
+
+        let (Pairing(pairs, _, _, _), candidate, rating) =
+            best_graph_pair(participants.clone(), &history, &options, seed, candidates).unwrap();
+        let (Pairing(pairs_again, _, _, _), candidate_again, rating_again) =
+            best_graph_pair(participants, &history, &options, seed, candidates).unwrap();
+        prop_assert_eq!(pairs, pairs_again);
+        prop_assert_eq!(candidate, candidate_again);
+        prop_assert_eq!(rating, rating_again);
+    }
+
+    #[test]
+    fn duplicates_are_rejected(
+        (participants, history) in participants_and_history(),
+        duplicate in any::<prop::sample::Index>(),
+        seed: u64,
+    ) {
+        let mut with_duplicate = participants.clone();
+        with_duplicate.push(*duplicate.get(&participants));
+        let options = MatchingOptions::default();
+        prop_assert!(graph_pair(with_duplicate.clone(), &history, &options, seed).is_err());
+        prop_assert!(best_graph_pair(with_duplicate, &history, &options, seed, 3).is_err());
+    }
+
+    #[test]
+    fn mentorship_invariants(
+        ((participants, history), (mentor_count, capacity)) in participants_and_history()
+            .prop_flat_map(|(participants, history)| {
+                let n = participants.len() as u32;
+                (Just((participants, history)), mentors_and_capacity(n))
+            }),
+        never in prop::collection::vec((0..80u32, 0..80u32), 0..5),
+        seed: u64,
+    ) {
+        let mentors: HashSet<u32> = (0..mentor_count).collect();
+        let options = MatchingOptions {
+            never_match: never.clone(),
+            mentorship: Some(Mentorship { mentors: mentors.clone(), capacity }),
+            ..Default::default()
+        };
+        // the rules may be impossible to satisfy, in which case an error is expected
+        let pairing = match graph_pair(participants.clone(), &history, &options, seed) {
+            Ok(pairing) => pairing,
+            Err(e) => {
+                prop_assert!(!never.is_empty(), "{}", e);
+                return Ok(());
+            }
+        };
+        check_groups(&pairing, &participants, &history)?;
+
+        // each group is one mentor followed by between one and `capacity` mentees
+        let Pairing(groups, ..) = pairing;
+        prop_assert_eq!(groups.len(), mentor_count as usize);
+        for group in &groups {
+            prop_assert!(mentors.contains(&group[0]));
+            prop_assert!(group[1..].iter().all(|m| !mentors.contains(m)));
+            prop_assert!((2..=capacity + 1).contains(&group.len()));
+            for (a, b) in &never {
+                prop_assert!(a == b || !(group.contains(a) && group.contains(b)));
+            }
+        }
+    }
+
+    #[test]
+    fn mentorship_needs_enough_mentors_and_mentees(n in 4..40u32, seed: u64) {
+        let options = |mentors: u32, capacity| MatchingOptions {
+            mentorship: Some(Mentorship { mentors: (0..mentors).collect(), capacity }),
+            ..Default::default()
+        };
+        let participants: Vec<u32> = (0..n).collect();
+        // more mentors than mentees
+        prop_assert!(graph_pair(participants.clone(), &[], &options(n / 2 + 1, 2), seed).is_err());
+        // more mentees than the mentors can take
+        prop_assert!(graph_pair(participants.clone(), &[], &options(1, 2), seed).is_err());
+        prop_assert!(graph_pair(participants, &[], &options(n / 2, 2), seed).is_ok());
+    }
+}
@@ -0,0 +1,108 @@
+use matchy_meetups_bot::guild_api::GuildMember;
+use matchy_meetups_bot::member_index::MemberIndex;
+use serenity::all::{GuildId, RoleId, UserId};
+
+const GUILD: GuildId = GuildId::new(1);
+const ROLE: RoleId = RoleId::new(10);
+const OTHER_ROLE: RoleId = RoleId::new(11);
+
+fn member(id: u64, roles: &[RoleId]) -> GuildMember {
+    GuildMember {
+        id: UserId::new(id),
+        roles: roles.to_vec(),
+        bot: false,
+        pending: false,
+        joined_at: None,
+    }
+}
+
+fn ids(members: Option<Vec<GuildMember>>) -> Option<Vec<u64>> {
+    members.map(|members| members.iter().map(|m| m.id.get()).collect())
+}
+
+#[test]
+fn members_are_indexed_by_role() {
+    let index = MemberIndex::default();
+    let needs_chunks = index.guild_created(
+        GUILD,
+        [
+            member(3, &[ROLE]),
+            member(2, &[ROLE, OTHER_ROLE]),
+            member(4, &[]),
+        ],
+        3,
+    );
+    assert!(!needs_chunks);
+    assert_eq!(ids(index.members_with_role(GUILD, ROLE)), Some(vec![2, 3]));
+    assert_eq!(
+        ids(index.members_with_role(GUILD, OTHER_ROLE)),
+        Some(vec![2])
+    );
+    assert_eq!(
+        ids(index.members_with_role(GUILD, RoleId::new(12))),
+        Some(vec![])
+    );
+    assert_eq!(ids(index.members_with_role(GuildId::new(2), ROLE)), None);
+}
+
+#[test]
+fn additions_updates_and_removals_are_applied() {
+    let index = MemberIndex::default();
+    index.guild_created(GUILD, [member(2, &[ROLE])], 1);
+
+    index.member_updated(GUILD, member(5, &[ROLE]));
+    assert_eq!(ids(index.members_with_role(GUILD, ROLE)), Some(vec![2, 5]));
+
+    index.member_updated(GUILD, member(2, &[OTHER_ROLE]));
+    assert_eq!(ids(index.members_with_role(GUILD, ROLE)), Some(vec![5]));
+    assert_eq!(
+        ids(index.members_with_role(GUILD, OTHER_ROLE)),
+        Some(vec![2])
+    );
+
+    index.member_removed(GUILD, UserId::new(5));
+    assert_eq!(ids(index.members_with_role(GUILD, ROLE)), Some(vec![]));
+
+    index.role_deleted(GUILD, OTHER_ROLE);
+    assert_eq!(
+        ids(index.members_with_role(GUILD, OTHER_ROLE)),
+        Some(vec![])
+    );
+    // a member updated after the role was deleted is not indexed under it again
+    index.member_updated(GUILD, member(2, &[ROLE]));
+    assert_eq!(ids(index.members_with_role(GUILD, ROLE)), Some(vec![2]));
+
+    index.guild_deleted(GUILD);
+    assert_eq!(ids(index.members_with_role(GUILD, ROLE)), None);
+}
+
+#[test]
+fn guilds_are_complete_once_every_chunk_arrives() {
+    let index = MemberIndex::default();
+    assert!(index.guild_created(GUILD, [member(2, &[ROLE])], 4));
+    assert_eq!(ids(index.members_with_role(GUILD, ROLE)), None);
+
+    // the last chunk can arrive before the others
+    index.members_chunk(GUILD, [member(5, &[ROLE])], 2, 3);
+    assert_eq!(ids(index.members_with_role(GUILD, ROLE)), None);
+    index.members_chunk(GUILD, [member(3, &[ROLE])], 0, 3);
+    assert_eq!(ids(index.members_with_role(GUILD, ROLE)), None);
+    index.members_chunk(GUILD, [member(4, &[ROLE])], 1, 3);
+    assert_eq!(
+        ids(index.members_with_role(GUILD, ROLE)),
+        Some(vec![2, 3, 4, 5])
+    );
+}
+
+#[test]
+fn chunks_replace_members_already_indexed() {
+    let index = MemberIndex::default();
+    index.guild_created(GUILD, [], 2);
+    index.member_updated(GUILD, member(2, &[ROLE]));
+    index.members_chunk(GUILD, [member(2, &[OTHER_ROLE]), member(3, &[ROLE])], 0, 1);
+    assert_eq!(ids(index.members_with_role(GUILD, ROLE)), Some(vec![3]));
+    assert_eq!(
+        ids(index.members_with_role(GUILD, OTHER_ROLE)),
+        Some(vec![2])
+    );
+}
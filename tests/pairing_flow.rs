@@ -0,0 +1,849 @@
+//! End-to-end tests of the /create_pairing → /send_pairing flow against an in-memory guild.
+
+use matchy_meetups_bot::audit::{record_audit, AuditEntry, PendingAudit};
+use matchy_meetups_bot::config::{
+    HISTORY_CHANNEL_NAME, MAX_SEED_LENGTH, NOTIFICATION_CHANNEL_NAME,
+};
+use matchy_meetups_bot::create_pairing::{handle_create_pairing, CreatedPairing};
+use matchy_meetups_bot::fake_guild::FakeGuild;
+use matchy_meetups_bot::guild_api::GuildApi;
+use matchy_meetups_bot::program::DEFAULT_PROGRAM;
+use matchy_meetups_bot::send_pairing::handle_send_pairing;
+use matchy_meetups_bot::storage::{
+    HistorySource, HistorySourceKind, Lookback, MentorshipSettings, ProgramData, Store,
+};
+use matchy_meetups_bot::types::Data;
+use matchy_meetups_bot::ROLE_NAME;
+use serenity::all::{ChannelId, RoleId, Timestamp, UserId};
+use std::ops::Deref;
+use tempfile::TempDir;
+
+struct TestGuild {
+    guild: FakeGuild,
+    role: RoleId,
+    notification_channel: ChannelId,
+    history_channel: ChannelId,
+}
+
+/// Creates a guild with the matchy meetups role, both channels, and `members` members with the
+/// role.
+fn test_guild(members: usize) -> TestGuild {
+    let mut guild = FakeGuild::new(42);
+    let role = guild.add_role(ROLE_NAME);
+    let notification_channel = guild.add_channel(NOTIFICATION_CHANNEL_NAME);
+    let history_channel = guild.add_channel(HISTORY_CHANNEL_NAME);
+    for i in 0..members {
+        guild.add_member(&format!("member {i}"), &[role]);
+    }
+    TestGuild {
+        guild,
+        role,
+        notification_channel,
+        history_channel,
+    }
+}
+
+/// Bot data with an empty store, saved in a temporary directory that is removed when the data is
+/// dropped.
+struct TestData {
+    data: Data,
+    _dir: TempDir,
+}
+
+impl Deref for TestData {
+    type Target = Data;
+
+    fn deref(&self) -> &Data {
+        &self.data
+    }
+}
+
+fn empty_data() -> TestData {
+    let dir = TempDir::new().expect("a temporary directory should be created");
+    let store =
+        Store::load(dir.path().join("store.json")).expect("a missing store file should be empty");
+    TestData {
+        data: Data::new(store),
+        _dir: dir,
+    }
+}
+
+async fn create_key(guild: &FakeGuild, data: &Data, seed: &str) -> String {
+    handle_create_pairing(guild, data, DEFAULT_PROGRAM, seed.to_owned(), 10, false)
+        .await
+        .expect("create_pairing should succeed")
+        .key
+}
+
+#[tokio::test]
+async fn create_then_send_notifies_and_records_history() {
+    let TestGuild {
+        guild,
+        role,
+        notification_channel,
+        history_channel,
+    } = test_guild(5);
+    let data = empty_data();
+
+    let CreatedPairing {
+        response: resp,
+        explanation,
+        key,
+    } = handle_create_pairing(
+        &guild,
+        &data,
+        DEFAULT_PROGRAM,
+        "2024-01-01".to_owned(),
+        10,
+        true,
+    )
+    .await
+    .unwrap();
+    assert!(resp.contains("Total paired members: 5"), "{resp}");
+    assert!(resp.contains("All members were matched with new people"));
+    let explanation = explanation.unwrap();
+    assert!(explanation.starts_with("**1.** "), "{explanation}");
+    assert_eq!(explanation.matches("(score ").count(), 2, "{explanation}");
+    assert_eq!(
+        explanation
+            .matches("- None of these members have met before")
+            .count(),
+        2,
+        "{explanation}"
+    );
+    assert_eq!(
+        explanation
+            .matches("was added as a third member because there was an odd number of members")
+            .count(),
+        1,
+        "{explanation}"
+    );
+
+    let resp = handle_send_pairing(&guild, &data, DEFAULT_PROGRAM, key)
+        .await
+        .unwrap();
+    assert_eq!(resp, "Successfully messaged 5 users.");
+
+    let notifications = guild.channel_contents(notification_channel);
+    assert_eq!(notifications.len(), 1);
+    assert!(notifications[0].starts_with(&format!("Hey <@&{role}>")));
+
+    let history = guild.channel_contents(history_channel);
+    assert_eq!(history.len(), 1);
+    assert!(history[0].starts_with("https://discord.com/channels/"));
+    assert!(history[0].contains("```matchy-round"));
+    for (member, _) in &guild.members {
+        assert!(history[0].contains(&format!("\"{}\"", member.id)));
+    }
+
+    let dms = guild.dms.lock().unwrap();
+    assert_eq!(dms.len(), 5);
+    assert!(dms
+        .iter()
+        .all(|(_, dm)| dm.contains("Your pairing is with:")));
+}
+
+#[tokio::test]
+async fn explanations_describe_repeats_and_the_remainder() {
+    let TestGuild {
+        guild,
+        history_channel,
+        ..
+    } = test_guild(3);
+    let ids: Vec<UserId> = guild.members.iter().map(|(m, _)| m.id).collect();
+    let (a, b, c) = (ids[0], ids[1], ids[2]);
+    // the three members have all met, so the only possible group repeats every pair
+    let message = guild.add_message(
+        history_channel,
+        guild.bot_id,
+        &format!("https://discord.com/channels/1/2/3\n<@{a}>, <@{b}>, and <@{c}>"),
+    );
+    let data = empty_data();
+
+    let explanation = handle_create_pairing(
+        &guild,
+        &data,
+        DEFAULT_PROGRAM,
+        "2024-01-01".to_owned(),
+        10,
+        true,
+    )
+    .await
+    .unwrap()
+    .explanation
+    .unwrap();
+    let met = {
+        let messages = guild.messages.lock().unwrap();
+        let timestamp = messages[&history_channel][0].timestamp.unix_timestamp();
+        format!("<t:{timestamp}:R> (history message `{message}`)")
+    };
+    assert_eq!(
+        explanation,
+        format!(
+            "**1.** <@{b}>, <@{c}>, <@{a}> (score 0)\n\
+            - Relaxed history: <@{b}> and <@{c}> last met {met}\n\
+            - Relaxed history: <@{b}> and <@{a}> last met {met}\n\
+            - Relaxed history: <@{c}> and <@{a}> last met {met}\n\
+            - <@{a}> was added as a third member because there was an odd number of members"
+        )
+    );
+}
+
+#[tokio::test]
+async fn sending_a_round_is_recorded_in_metrics() {
+    let TestGuild { guild, .. } = test_guild(5);
+    let data = empty_data();
+
+    let key = create_key(&guild, &data, "2024-01-01").await;
+    handle_send_pairing(&guild, &data, DEFAULT_PROGRAM, key)
+        .await
+        .unwrap();
+
+    let metrics = &data.metrics;
+    assert_eq!(metrics.rounds_sent.get(), 1);
+    assert_eq!(
+        metrics
+            .direct_messages
+            .with_label_values(&["delivered"])
+            .get(),
+        5
+    );
+    assert_eq!(metrics.round_participants.get_sample_sum(), 5.0);
+    // once for /create_pairing and once for /send_pairing
+    assert_eq!(metrics.matching_duration.get_sample_count(), 2);
+    let rendered = metrics.render();
+    assert!(
+        rendered.contains("matchy_rounds_sent_total 1"),
+        "{rendered}"
+    );
+}
+
+#[tokio::test]
+async fn history_from_sent_rounds_is_used() {
+    let TestGuild { guild, .. } = test_guild(4);
+    let data = empty_data();
+
+    let key = create_key(&guild, &data, "round 1").await;
+    handle_send_pairing(&guild, &data, DEFAULT_PROGRAM, key)
+        .await
+        .unwrap();
+
+    // with four members there are three disjoint rounds, so the second round can avoid repeats
+    let resp = handle_create_pairing(
+        &guild,
+        &data,
+        DEFAULT_PROGRAM,
+        "round 2".to_owned(),
+        10,
+        false,
+    )
+    .await
+    .unwrap()
+    .response;
+    assert!(
+        resp.contains("All members were matched with new people"),
+        "{resp}"
+    );
+}
+
+#[tokio::test]
+async fn ineligible_members_are_excluded() {
+    let TestGuild {
+        mut guild, role, ..
+    } = test_guild(4);
+    let data = empty_data();
+    data.store
+        .update_program(guild.id, DEFAULT_PROGRAM, |program| {
+            program.settings.min_member_days = 7
+        })
+        .unwrap();
+    let bot = guild.add_member("bot", &[role]);
+    guild.member_mut(bot).bot = true;
+    let pending = guild.add_member("pending", &[role]);
+    guild.member_mut(pending).pending = true;
+    let new_member = guild.add_member("new member", &[role]);
+    guild.member_mut(new_member).joined_at = Some(Timestamp::now());
+    let old_member = guild.add_member("old member", &[role]);
+    guild.member_mut(old_member).joined_at = Some(
+        Timestamp::from_unix_timestamp(Timestamp::now().unix_timestamp() - 8 * 86400).unwrap(),
+    );
+    // a member returned twice by the API is only matched once
+    let duplicate = guild.members[0].clone();
+    guild.members.push(duplicate);
+
+    let resp = handle_create_pairing(
+        &guild,
+        &data,
+        DEFAULT_PROGRAM,
+        "2024-01-01".to_owned(),
+        10,
+        false,
+    )
+    .await
+    .unwrap()
+    .response;
+    assert!(resp.contains("Total paired members: 5"), "{resp}");
+    assert!(resp.contains(&format!("<@{bot}> (bot account)")), "{resp}");
+    assert!(resp.contains(&format!(
+        "<@{pending}> (has not passed membership screening)"
+    )));
+    assert!(resp.contains(&format!("<@{new_member}> (joined less than 7 days ago)")));
+    assert!(!resp.contains(&format!("<@{old_member}> (")));
+}
+
+#[tokio::test]
+async fn key_mismatch_after_members_change() {
+    let TestGuild {
+        mut guild,
+        role,
+        notification_channel,
+        ..
+    } = test_guild(6);
+    let data = empty_data();
+
+    let key = create_key(&guild, &data, "2024-01-01").await;
+    guild.add_member("late joiner", &[role]);
+
+    let err = handle_send_pairing(&guild, &data, DEFAULT_PROGRAM, key)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().starts_with("Key mismatch"), "{err}");
+    assert!(guild.channel_contents(notification_channel).is_empty());
+    assert!(guild.dms.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn invalid_key_is_rejected() {
+    let TestGuild { guild, .. } = test_guild(4);
+    let data = empty_data();
+
+    let err = handle_send_pairing(&guild, &data, DEFAULT_PROGRAM, "no checksum".to_owned())
+        .await
+        .unwrap_err();
+    assert!(err.to_string().starts_with("Invalid key"), "{err}");
+}
+
+#[tokio::test]
+async fn missing_notification_channel() {
+    let TestGuild { mut guild, .. } = test_guild(4);
+    let data = empty_data();
+
+    let key = create_key(&guild, &data, "2024-01-01").await;
+    guild.channels.remove(NOTIFICATION_CHANNEL_NAME);
+
+    let err = handle_send_pairing(&guild, &data, DEFAULT_PROGRAM, key)
+        .await
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Could not find notification channel");
+    assert!(guild.dms.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn missing_history_channel() {
+    let TestGuild { mut guild, .. } = test_guild(4);
+    let data = empty_data();
+    guild.channels.remove(HISTORY_CHANNEL_NAME);
+
+    let err = handle_create_pairing(
+        &guild,
+        &data,
+        DEFAULT_PROGRAM,
+        "2024-01-01".to_owned(),
+        10,
+        false,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.to_string(), "Could not find history channel");
+}
+
+#[tokio::test]
+async fn too_few_members() {
+    let TestGuild { guild, .. } = test_guild(1);
+    let data = empty_data();
+
+    let err = handle_create_pairing(
+        &guild,
+        &data,
+        DEFAULT_PROGRAM,
+        "2024-01-01".to_owned(),
+        10,
+        false,
+    )
+    .await
+    .unwrap_err();
+    assert!(
+        err.to_string().starts_with("Need at least two members"),
+        "{err}"
+    );
+}
+
+#[tokio::test]
+async fn admin_actions_are_recorded_in_the_audit_channel() {
+    let TestGuild { mut guild, .. } = test_guild(2);
+    let audit_channel = guild.add_channel("audit");
+    let data = empty_data();
+    let entry = AuditEntry {
+        actor: UserId::new(7),
+        action: "/send_pairing".to_owned(),
+        parameters: vec![("key".to_owned(), "2024-01-01_abc".to_owned())],
+        outcome: "Successfully messaged 2 users.".to_owned(),
+        time: Timestamp::from_unix_timestamp(1_700_000_000).unwrap(),
+    };
+
+    // nothing is recorded until an audit channel is configured
+    record_audit(&guild, &data, &entry).await;
+    assert!(guild.channel_contents(audit_channel).is_empty());
+
+    data.store
+        .update(guild.guild_id(), |data| {
+            data.settings.audit_channel = Some(audit_channel)
+        })
+        .unwrap();
+    record_audit(&guild, &data, &entry).await;
+    assert_eq!(
+        guild.channel_contents(audit_channel),
+        vec![
+            "**/send_pairing** by <@7> at <t:1700000000:F>\n\
+            **Parameters:** key: `2024-01-01_abc`\n\
+            **Outcome:** Successfully messaged 2 users."
+        ]
+    );
+}
+
+#[tokio::test]
+async fn programs_have_their_own_members_channels_and_history() {
+    let TestGuild {
+        mut guild,
+        notification_channel,
+        history_channel,
+        ..
+    } = test_guild(4);
+    let mentors = guild.add_role("mentors");
+    let mentor_notifications = guild.add_channel("mentor-announcements");
+    let mentor_history = guild.add_channel("mentor-history");
+    let mentor_ids: Vec<UserId> = (0..3)
+        .map(|i| guild.add_member(&format!("mentor {i}"), &[mentors]))
+        .collect();
+    let data = empty_data();
+    data.store
+        .update(guild.id, |data| {
+            let program = ProgramData {
+                role: Some(mentors),
+                notification_channel: Some(mentor_notifications),
+                history_channel: Some(mentor_history),
+                ..ProgramData::default()
+            };
+            data.create_program("mentors", program, None)
+        })
+        .unwrap()
+        .unwrap();
+    data.store
+        .update_program(guild.id, "mentors", |program| {
+            program.settings.notification_template = Some("Mentor pairs:\n{pairs}".to_owned())
+        })
+        .unwrap();
+
+    let created =
+        handle_create_pairing(&guild, &data, "mentors", "2024-01-01".to_owned(), 10, false)
+            .await
+            .unwrap();
+    assert!(
+        created.response.contains("Total paired members: 3"),
+        "{}",
+        created.response
+    );
+    handle_send_pairing(&guild, &data, "mentors", created.key)
+        .await
+        .unwrap();
+
+    let notifications = guild.channel_contents(mentor_notifications);
+    assert_eq!(notifications.len(), 1);
+    assert!(notifications[0].starts_with("Mentor pairs:\n"));
+    let history = guild.channel_contents(mentor_history);
+    assert_eq!(history.len(), 1);
+    for mentor in &mentor_ids {
+        assert!(history[0].contains(&format!("\"{mentor}\"")));
+    }
+    assert!(guild.channel_contents(notification_channel).is_empty());
+    assert!(guild.channel_contents(history_channel).is_empty());
+
+    // a round created right after the last one is flagged
+    let resp = handle_create_pairing(&guild, &data, "mentors", "2024-01-02".to_owned(), 10, false)
+        .await
+        .unwrap()
+        .response;
+    assert!(
+        resp.contains("The last round of `mentors` was sent 0 day(s) ago"),
+        "{resp}"
+    );
+    // while the default program is unaffected
+    let resp = handle_create_pairing(
+        &guild,
+        &data,
+        DEFAULT_PROGRAM,
+        "2024-01-02".to_owned(),
+        10,
+        false,
+    )
+    .await
+    .unwrap()
+    .response;
+    assert!(resp.contains("Total paired members: 4"), "{resp}");
+    assert!(!resp.contains("The last round"), "{resp}");
+}
+
+#[tokio::test]
+async fn unknown_programs_are_rejected() {
+    let TestGuild { guild, .. } = test_guild(4);
+    let data = empty_data();
+
+    let err = handle_create_pairing(&guild, &data, "mentors", "2024-01-01".to_owned(), 10, false)
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .starts_with("There is no program named `mentors`"),
+        "{err}"
+    );
+}
+
+#[tokio::test]
+async fn extra_history_sources_are_avoided_by_weight() {
+    let TestGuild { guild, .. } = test_guild(4);
+    let data = empty_data();
+    let [a, b, c, d] = [0, 1, 2, 3].map(|i| guild.members[i].0.id);
+    let file = |name: &str, groups: Vec<Vec<UserId>>, weight| HistorySource {
+        name: name.to_owned(),
+        kind: HistorySourceKind::File {
+            uploaded: Timestamp::now(),
+            groups,
+        },
+        lookback: Lookback::Days(30),
+        weight,
+    };
+    // every possible pairing repeats a pair, but the hackathon teams count the most
+    data.store
+        .update_program(guild.id, DEFAULT_PROGRAM, |program| {
+            program.history_sources = vec![
+                file("hackathon", vec![vec![a, b], vec![c, d]], 3),
+                file(
+                    "retreat",
+                    vec![vec![a, c], vec![b, d], vec![a, d], vec![b, c]],
+                    1,
+                ),
+            ]
+        })
+        .unwrap();
+
+    for seed in ["1", "2", "3", "4"] {
+        let resp = handle_create_pairing(&guild, &data, DEFAULT_PROGRAM, seed.to_owned(), 1, false)
+            .await
+            .unwrap()
+            .response;
+        assert!(resp.contains("Repeated pairs: 2 ("), "{resp}");
+        for (x, y) in [(a, b), (c, d)] {
+            assert!(
+                !resp.contains(&format!("<@{x}> and <@{y}>"))
+                    && !resp.contains(&format!("<@{y}> and <@{x}>")),
+                "{resp}"
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn expired_history_files_are_ignored() {
+    let TestGuild { guild, .. } = test_guild(2);
+    let data = empty_data();
+    let [a, b] = [0, 1].map(|i| guild.members[i].0.id);
+    let uploaded =
+        Timestamp::from_unix_timestamp(Timestamp::now().unix_timestamp() - 10 * 86400).unwrap();
+    data.store
+        .update_program(guild.id, DEFAULT_PROGRAM, |program| {
+            program.history_sources = vec![HistorySource {
+                name: "retreat".to_owned(),
+                kind: HistorySourceKind::File {
+                    uploaded,
+                    groups: vec![vec![a, b]],
+                },
+                lookback: Lookback::Days(7),
+                weight: 1,
+            }]
+        })
+        .unwrap();
+
+    let resp = handle_create_pairing(&guild, &data, DEFAULT_PROGRAM, "1".to_owned(), 1, false)
+        .await
+        .unwrap()
+        .response;
+    assert!(
+        resp.contains("All members were matched with new people"),
+        "{resp}"
+    );
+}
+
+#[tokio::test]
+async fn mentors_are_matched_with_mentees() {
+    let TestGuild { mut guild, .. } = test_guild(4);
+    let mentor_role = guild.add_role("mentors");
+    let mentors: Vec<UserId> = (0..2)
+        .map(|i| guild.add_member(&format!("mentor {i}"), &[mentor_role]))
+        .collect();
+    let data = empty_data();
+    data.store
+        .update_program(guild.id, DEFAULT_PROGRAM, |program| {
+            program.settings.mentorship = Some(MentorshipSettings {
+                mentor_role,
+                mentees_per_mentor: 2,
+            })
+        })
+        .unwrap();
+
+    let created = handle_create_pairing(&guild, &data, DEFAULT_PROGRAM, "1".to_owned(), 10, false)
+        .await
+        .unwrap();
+    let resp = &created.response;
+    assert!(resp.contains("Total paired members: 6"), "{resp}");
+    // each group starts with its mentor, followed by two mentees
+    for mentor in &mentors {
+        let group = resp
+            .lines()
+            .find(|line| line.starts_with(&format!("<@{mentor}>, ")))
+            .unwrap_or_else(|| panic!("no group for <@{mentor}>: {resp}"));
+        assert_eq!(group.matches("<@").count(), 3, "{resp}");
+    }
+    handle_send_pairing(&guild, &data, DEFAULT_PROGRAM, created.key)
+        .await
+        .unwrap();
+
+    // two mentors with one mentee each can't take all four mentees
+    data.store
+        .update_program(guild.id, DEFAULT_PROGRAM, |program| {
+            program.settings.mentorship = Some(MentorshipSettings {
+                mentor_role,
+                mentees_per_mentor: 1,
+            })
+        })
+        .unwrap();
+    let err = handle_create_pairing(&guild, &data, DEFAULT_PROGRAM, "2".to_owned(), 10, false)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "2 mentors can take at most 2 mentees, but there are 4."
+    );
+}
+
+#[tokio::test]
+async fn seeds_that_would_break_the_history_record_are_rejected() {
+    let TestGuild { guild, .. } = test_guild(4);
+    let data = empty_data();
+
+    for (seed, error) in [
+        ("a```b", "The seed can't contain backticks (`)."),
+        (
+            &"x".repeat(MAX_SEED_LENGTH + 1),
+            "The seed can be at most 100 characters long.",
+        ),
+    ] {
+        let err = handle_create_pairing(&guild, &data, DEFAULT_PROGRAM, seed.to_owned(), 10, false)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), error);
+    }
+    let err = handle_send_pairing(&guild, &data, DEFAULT_PROGRAM, "a```b_0123abcd".to_owned())
+        .await
+        .unwrap_err();
+    assert!(err.to_string().starts_with("Invalid key"), "{err}");
+}
+
+#[tokio::test]
+async fn rounds_with_the_longest_seed_are_recorded() {
+    let TestGuild {
+        guild,
+        history_channel,
+        ..
+    } = test_guild(4);
+    let data = empty_data();
+
+    // multi-byte characters make the key as long as possible in bytes
+    let key = create_key(&guild, &data, &"🎉".repeat(MAX_SEED_LENGTH)).await;
+    handle_send_pairing(&guild, &data, DEFAULT_PROGRAM, key)
+        .await
+        .unwrap();
+    assert_eq!(guild.channel_contents(history_channel).len(), 1);
+
+    // the round was readable, so the next one avoids repeating it
+    let resp = handle_create_pairing(
+        &guild,
+        &data,
+        DEFAULT_PROGRAM,
+        "round 2".to_owned(),
+        10,
+        false,
+    )
+    .await
+    .unwrap()
+    .response;
+    assert!(
+        resp.contains("All members were matched with new people"),
+        "{resp}"
+    );
+}
+
+#[tokio::test]
+async fn created_and_sent_pairings_are_recorded_in_the_audit_channel() {
+    let TestGuild { mut guild, .. } = test_guild(4);
+    let audit_channel = guild.add_channel("audit");
+    let data = empty_data();
+    data.store
+        .update(guild.guild_id(), |data| {
+            data.settings.audit_channel = Some(audit_channel)
+        })
+        .unwrap();
+    let entry = |action: &str, parameters: Vec<(&str, &str)>| AuditEntry {
+        actor: UserId::new(7),
+        action: action.to_owned(),
+        parameters: parameters
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect(),
+        outcome: "No response".to_owned(),
+        time: Timestamp::from_unix_timestamp(1_700_000_000).unwrap(),
+    };
+
+    let mut create = PendingAudit::start(
+        &guild,
+        &data,
+        entry("/create_pairing", vec![("seed", "audited")]),
+    );
+    let key = create_key(&guild, &data, "audited").await;
+    create.set_outcome(format!("Created a pairing with key `{key}`"));
+    create.record(&guild).await;
+
+    let mut send = PendingAudit::start(&guild, &data, entry("/send_pairing", vec![("key", &key)]));
+    // turning off the audit log during an action still records it in the old channel
+    data.store
+        .update(guild.guild_id(), |data| data.settings.audit_channel = None)
+        .unwrap();
+    send.set_outcome(
+        handle_send_pairing(&guild, &data, DEFAULT_PROGRAM, key.clone())
+            .await
+            .unwrap(),
+    );
+    send.record(&guild).await;
+
+    assert_eq!(
+        guild.channel_contents(audit_channel),
+        vec![
+            format!(
+                "**/create_pairing** by <@7> at <t:1700000000:F>\n\
+                **Parameters:** seed: `audited`\n\
+                **Outcome:** Created a pairing with key `{key}`"
+            ),
+            format!(
+                "**/send_pairing** by <@7> at <t:1700000000:F>\n\
+                **Parameters:** key: `{key}`\n\
+                **Outcome:** Successfully messaged 4 users."
+            ),
+        ]
+    );
+
+    // later actions are not recorded
+    let pending = PendingAudit::start(&guild, &data, entry("/send_pairing", vec![]));
+    pending.record(&guild).await;
+    assert_eq!(guild.channel_contents(audit_channel).len(), 2);
+}
+
+#[tokio::test]
+async fn only_organizers_who_can_create_are_given_the_key() {
+    let TestGuild { guild, .. } = test_guild(4);
+    let data = empty_data();
+    let created = handle_create_pairing(
+        &guild,
+        &data,
+        DEFAULT_PROGRAM,
+        "reply".to_owned(),
+        10,
+        false,
+    )
+    .await
+    .unwrap();
+
+    let draft = created.reply(false);
+    assert_eq!(
+        draft,
+        format!(
+            "{}\nThis is a draft. Creating a pairing that can be sent needs the `create` \
+            permission.",
+            created.response
+        )
+    );
+    assert!(!draft.contains(&created.key));
+
+    let reply = created.reply(true);
+    let (response, key_line) = reply.rsplit_once('\n').unwrap();
+    assert_eq!(response, created.response);
+    let key = key_line
+        .strip_prefix("To send this pairing, use this key: `")
+        .and_then(|rest| rest.strip_suffix('`'))
+        .unwrap();
+    assert_eq!(key, created.key);
+
+    // the key in the reply sends the pairing
+    let resp = handle_send_pairing(&guild, &data, DEFAULT_PROGRAM, key.to_owned())
+        .await
+        .unwrap();
+    assert_eq!(resp, "Successfully messaged 4 users.");
+}
+
+#[tokio::test]
+async fn actions_that_fail_after_sending_are_recorded() {
+    let TestGuild { mut guild, .. } = test_guild(4);
+    let audit_channel = guild.add_channel("audit");
+    let data = empty_data();
+    data.store
+        .update(guild.guild_id(), |data| {
+            data.settings.audit_channel = Some(audit_channel)
+        })
+        .unwrap();
+    let key = create_key(&guild, &data, "failed reply").await;
+    let entry = |action: &str| AuditEntry {
+        actor: UserId::new(7),
+        action: action.to_owned(),
+        parameters: Vec::new(),
+        outcome: "No response".to_owned(),
+        time: Timestamp::from_unix_timestamp(1_700_000_000).unwrap(),
+    };
+
+    // the round is sent, but replying to the organizer fails
+    let mut send = PendingAudit::start(&guild, &data, entry("/send_pairing"));
+    send.set_outcome(
+        handle_send_pairing(&guild, &data, DEFAULT_PROGRAM, key)
+            .await
+            .unwrap(),
+    );
+    send.set_error(&anyhow::anyhow!("Unknown interaction"));
+    send.record(&guild).await;
+
+    // an action that fails before setting an outcome records the error
+    let mut failed = PendingAudit::start(&guild, &data, entry("/matchy_settings cadence"));
+    failed.set_error(&anyhow::anyhow!("Unable to save"));
+    failed.record(&guild).await;
+
+    let contents = guild.channel_contents(audit_channel);
+    assert_eq!(contents.len(), 2);
+    assert!(
+        contents[0].ends_with(
+            "**Outcome:** Successfully messaged 4 users.\nThen failed: Unknown interaction"
+        ),
+        "{}",
+        contents[0]
+    );
+    assert!(
+        contents[1].ends_with("**Outcome:** Error: Unable to save"),
+        "{}",
+        contents[1]
+    );
+}
@@ -0,0 +1,45 @@
+//! Tests of which members can use matchy meetups commands.
+
+use matchy_meetups_bot::permissions::has_level;
+use matchy_meetups_bot::storage::PermissionLevel::{self, Configure, Create, Send, View};
+use serenity::all::RoleId;
+
+const ORGANIZER: RoleId = RoleId::new(10);
+const DRAFTER: RoleId = RoleId::new(11);
+const OTHER: RoleId = RoleId::new(12);
+
+fn organizer_roles() -> Vec<(RoleId, PermissionLevel)> {
+    vec![(ORGANIZER, Send), (DRAFTER, View)]
+}
+
+#[test]
+fn levels_include_the_ones_before_them() {
+    let roles = organizer_roles();
+    for level in [View, Create, Send] {
+        assert!(has_level(level, false, &[ORGANIZER], &roles));
+    }
+    assert!(!has_level(Configure, false, &[ORGANIZER], &roles));
+    assert!(has_level(View, false, &[DRAFTER], &roles));
+    assert!(!has_level(Create, false, &[DRAFTER], &roles));
+}
+
+#[test]
+fn the_highest_level_of_any_role_applies() {
+    assert!(has_level(
+        Send,
+        false,
+        &[DRAFTER, ORGANIZER],
+        &organizer_roles()
+    ));
+}
+
+#[test]
+fn other_members_have_no_levels() {
+    assert!(!has_level(View, false, &[OTHER], &organizer_roles()));
+    assert!(!has_level(View, false, &[ORGANIZER], &[]));
+}
+
+#[test]
+fn administrators_have_every_level() {
+    assert!(has_level(Configure, true, &[], &[]));
+}
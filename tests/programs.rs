@@ -0,0 +1,111 @@
+//! Tests of how programs are stored, including data saved before there were multiple programs.
+
+use matchy_meetups_bot::program::DEFAULT_PROGRAM;
+use matchy_meetups_bot::storage::{GuildData, Lookback, ProgramData};
+use serenity::all::{ChannelId, RoleId, UserId};
+
+#[test]
+fn legacy_data_moves_into_the_default_program() {
+    let legacy = r#"{
+        "never_match": [["1", "2"]],
+        "prefer_match": [],
+        "attributes": [],
+        "profiles": {},
+        "settings": {
+            "min_member_days": 7,
+            "history_lookback": { "rounds": 3 },
+            "audit_channel": "99",
+            "organizer_roles": []
+        }
+    }"#;
+    let data: GuildData = serde_json::from_str(legacy).unwrap();
+
+    let program = data.program(DEFAULT_PROGRAM).unwrap();
+    assert_eq!(program.never_match, vec![(UserId::new(1), UserId::new(2))]);
+    assert_eq!(program.settings.min_member_days, 7);
+    assert_eq!(program.settings.history_lookback, Lookback::Rounds(3));
+    assert_eq!(data.settings.audit_channel.map(|c| c.get()), Some(99));
+    assert_eq!(data.program_names(), vec![DEFAULT_PROGRAM]);
+
+    // saving and loading again keeps the migrated program
+    let saved = serde_json::to_string(&data).unwrap();
+    let reloaded: GuildData = serde_json::from_str(&saved).unwrap();
+    let program = reloaded.program(DEFAULT_PROGRAM).unwrap();
+    assert_eq!(program.settings.min_member_days, 7);
+}
+
+#[test]
+fn programs_can_be_created_and_removed() {
+    let mut data = GuildData::default();
+    let mentors = ProgramData {
+        role: Some(RoleId::new(5)),
+        ..ProgramData::default()
+    };
+    data.create_program(" mentors ", mentors.clone(), None)
+        .unwrap();
+    assert_eq!(data.program_names(), vec![DEFAULT_PROGRAM, "mentors"]);
+    assert_eq!(data.program("mentors").unwrap().role, Some(RoleId::new(5)));
+
+    let err = data
+        .create_program("mentors", mentors.clone(), None)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "There is already a program named `mentors`."
+    );
+    let err = data
+        .create_program(DEFAULT_PROGRAM, mentors, None)
+        .unwrap_err();
+    assert!(err.to_string().starts_with("There is already"), "{err}");
+
+    data.remove_program("mentors").unwrap();
+    assert!(data.program("mentors").is_none());
+    let err = data.remove_program("mentors").unwrap_err();
+    assert!(
+        err.to_string().starts_with("There is no program named"),
+        "{err}"
+    );
+    let err = data.remove_program(DEFAULT_PROGRAM).unwrap_err();
+    assert!(err.to_string().contains("can't be removed"), "{err}");
+}
+
+#[test]
+fn programs_need_their_own_history_channel() {
+    let named_history = Some(ChannelId::new(10));
+    let with_history = |channel: u64| ProgramData {
+        history_channel: Some(ChannelId::new(channel)),
+        ..ProgramData::default()
+    };
+    let mut data = GuildData::default();
+
+    // the default program records rounds in the channel found by name
+    let err = data
+        .create_program("mentors", with_history(10), named_history)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "<#10> is already the history channel of `matchy-meetups`."
+    );
+    data.create_program("mentors", with_history(11), named_history)
+        .unwrap();
+    let err = data
+        .create_program("officers", with_history(11), named_history)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "<#11> is already the history channel of `mentors`."
+    );
+
+    // once the default program has its own channel, the named one is free
+    data.program_mut(DEFAULT_PROGRAM).unwrap().history_channel = Some(ChannelId::new(12));
+    data.create_program("officers", with_history(10), named_history)
+        .unwrap();
+    let err = data
+        .create_program("alumni", with_history(12), named_history)
+        .unwrap_err();
+    assert!(err.to_string().contains("`matchy-meetups`"), "{err}");
+    assert_eq!(
+        data.program_names(),
+        vec![DEFAULT_PROGRAM, "mentors", "officers"]
+    );
+}